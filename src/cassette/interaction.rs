@@ -0,0 +1,340 @@
+use crate::errors::ShowMeErrors;
+use crate::ping_logs::query_filter::{Literal, QueryFilter};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+const REDACTED: &str = "REDACTED";
+const TOKEN_PATH: &str = "/am/oauth2/access_token";
+/// Response fields blanked wherever they appear, matched case-insensitively: credentials and
+/// session cookies, then who signed in and from where in log records.
+const REDACTED_FIELDS: [&str; 16] = [
+  "access_token",
+  "id_token",
+  "refresh_token",
+  "client_secret",
+  "password",
+  "userpassword",
+  "authorization",
+  "cookie",
+  "set-cookie",
+  "x-api-key",
+  "x-api-secret",
+  "userid",
+  "username",
+  "principal",
+  "ip",
+  "ipaddress",
+];
+/// Objects whose values are all blanked, keeping only their keys: the request and response
+/// headers access logs carry under `http`.
+const REDACTED_MAPS: [&str; 1] = ["headers"];
+/// Script responses carry the script's source in `script`; node configs use the same field for
+/// the script's id, which replays need.
+const SCRIPTS_PATH: &str = "/scripts/";
+/// Response headers worth keeping: the body's type and what the rate limiter reads.
+const KEPT_HEADERS: [&str; 5] = [
+  "content-type",
+  "retry-after",
+  "x-ratelimit-limit",
+  "x-ratelimit-remaining",
+  "x-ratelimit-reset",
+];
+/// The query parameter whose literals name who a search was about.
+const QUERY_FILTER: &str = "_queryFilter";
+/// Varies between runs for the same question, e.g. `-1h` resolved at request time.
+const TIME_PARAMS: [&str; 2] = ["beginTime=", "endTime="];
+
+/// The first line of a cassette: what was recorded and how to stand it back up.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CassetteHeader {
+  pub(crate) environment: String,
+  /// Where the logs API sat on the recorded tenant, e.g. `/monitoring/logs`.
+  pub(crate) log_path: String,
+  pub(crate) realms: Vec<String>,
+  pub(crate) recorded_at: DateTime<Utc>,
+}
+
+/// One upstream request and the response it got, without credentials. Request headers are never
+/// kept, since all they carry beyond the body's type is the bearer token and the logs API key pair.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Interaction {
+  pub(crate) method: String,
+  /// Path and query as sent, e.g. `/monitoring/logs?source=am-everything&transactionId=...`, with
+  /// the redacted fields' literals in `_queryFilter` blanked.
+  pub(crate) uri: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) request_body: Option<String>,
+  pub(crate) status: u16,
+  #[serde(default)]
+  pub(crate) headers: Vec<(String, String)>,
+  pub(crate) body: String,
+}
+
+/// Whether a response header is worth passing on and keeping.
+pub(crate) fn kept_header(name: &str) -> bool {
+  KEPT_HEADERS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+/// The part of a request body worth keeping: none for token requests, whose body is the signed
+/// assertion, or for empty bodies.
+pub(crate) fn recorded_body(uri: &str, body: &[u8]) -> Option<String> {
+  if body.is_empty() || uri.starts_with(TOKEN_PATH) {
+    return None;
+  }
+  Some(String::from_utf8_lossy(body).into_owned())
+}
+
+/// Replaces every string in `value` with `REDACTED`, keeping its shape so the app still reads it.
+fn blank(value: &mut serde_json::Value) {
+  match value {
+    serde_json::Value::String(text) => *text = REDACTED.to_string(),
+    serde_json::Value::Array(items) => items.iter_mut().for_each(blank),
+    serde_json::Value::Object(fields) => fields.values_mut().for_each(blank),
+    _ => {}
+  }
+}
+
+/// Blanks the redacted fields and maps at any depth, e.g. `payload.http.request.headers`.
+fn redact(value: &mut serde_json::Value) {
+  match value {
+    serde_json::Value::Object(fields) => {
+      for (name, field) in fields.iter_mut() {
+        if redacted_field(name) || REDACTED_MAPS.contains(&name.to_ascii_lowercase().as_str()) {
+          blank(field);
+        } else {
+          redact(field);
+        }
+      }
+    }
+    serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+    _ => {}
+  }
+}
+
+fn redacted_field(name: &str) -> bool {
+  REDACTED_FIELDS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+/// Blanks what a filter compares redacted fields to, e.g. the name in `/payload/principal eq "alice"`.
+fn blank_literals(filter: QueryFilter) -> QueryFilter {
+  match filter {
+    QueryFilter::Compare {
+      pointer,
+      operator,
+      value: Literal::String(_),
+    } if pointer.field().is_some_and(redacted_field) => QueryFilter::Compare {
+      pointer,
+      operator,
+      value: REDACTED.into(),
+    },
+    QueryFilter::And(filters) => {
+      QueryFilter::And(filters.into_iter().map(blank_literals).collect())
+    }
+    QueryFilter::Or(filters) => QueryFilter::Or(filters.into_iter().map(blank_literals).collect()),
+    QueryFilter::Not(filter) => QueryFilter::Not(Box::new(blank_literals(*filter))),
+    filter => filter,
+  }
+}
+
+/// Blanks the redacted fields' literals in a request's `_queryFilter`, or the whole filter if it
+/// doesn't parse. The rest of the path and query is kept as sent.
+fn redact_uri(uri: &str) -> String {
+  let Some((path, _)) = uri.split_once('?') else {
+    return uri.to_string();
+  };
+  let Ok(mut url) = Url::parse(&format!("http://cassette{uri}")) else {
+    return uri.to_string();
+  };
+
+  let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+  let redacted: Vec<(String, String)> = params
+    .iter()
+    .cloned()
+    .map(|(name, value)| match name.as_str() {
+      QUERY_FILTER => {
+        let value = value.parse().map_or(REDACTED.to_string(), |filter| {
+          blank_literals(filter).to_string()
+        });
+        (name, value)
+      }
+      _ => (name, value),
+    })
+    .collect();
+  if redacted == params {
+    return uri.to_string();
+  }
+
+  url.query_pairs_mut().clear().extend_pairs(&redacted);
+  format!("{path}?{}", url.query().unwrap_or_default())
+}
+
+/// Blanks credentials, personal details and script source in a JSON response body; other bodies,
+/// such as plain text log payloads and prometheus metrics, pass through.
+fn redact_body(uri: &str, body: &str) -> String {
+  let Ok(mut value) = serde_json::from_str::<serde_json::Value>(body) else {
+    return body.to_string();
+  };
+
+  redact(&mut value);
+  let path = uri.split_once('?').map_or(uri, |(path, _)| path);
+  if path.contains(SCRIPTS_PATH)
+    && let Some(source) = value.get_mut("script")
+  {
+    blank(source);
+  }
+  value.to_string()
+}
+
+impl Interaction {
+  pub(crate) fn new(
+    method: &str,
+    uri: &str,
+    request_body: &[u8],
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: &[u8],
+  ) -> Self {
+    Interaction {
+      method: method.to_string(),
+      uri: redact_uri(uri),
+      request_body: recorded_body(uri, request_body),
+      status,
+      headers: headers
+        .into_iter()
+        .filter(|(name, _)| kept_header(name))
+        .collect(),
+      body: redact_body(uri, &String::from_utf8_lossy(body)),
+    }
+  }
+
+  /// Identifies the request exactly, body included, as far as redaction left it to tell apart.
+  pub(crate) fn key(method: &str, uri: &str, request_body: Option<&str>) -> String {
+    format!(
+      "{method} {} {}",
+      redact_uri(uri),
+      request_body.unwrap_or_default()
+    )
+  }
+
+  /// Identifies the request regardless of its time window, for replays asking about "the last
+  /// hour" from a different now.
+  pub(crate) fn timeless_key(method: &str, uri: &str, request_body: Option<&str>) -> String {
+    let uri = match uri.split_once('?') {
+      Some((path, query)) => {
+        let query: Vec<&str> = query
+          .split('&')
+          .filter(|param| !TIME_PARAMS.iter().any(|time| param.starts_with(time)))
+          .collect();
+        format!("{path}?{}", query.join("&"))
+      }
+      None => uri.to_string(),
+    };
+    Interaction::key(method, &uri, request_body)
+  }
+}
+
+/// Appends interactions to a cassette as they happen, so a crash still leaves a usable file.
+pub(crate) struct CassetteWriter {
+  file: Mutex<File>,
+}
+
+impl CassetteWriter {
+  /// Starts a new cassette at `path`, replacing any file already there.
+  pub(crate) fn create(path: &str, header: &CassetteHeader) -> Result<Self, ShowMeErrors> {
+    let mut file = OpenOptions::new()
+      .create(true)
+      .write(true)
+      .truncate(true)
+      .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(header)?)?;
+
+    Ok(CassetteWriter {
+      file: Mutex::new(file),
+    })
+  }
+
+  pub(crate) fn append(&self, interaction: &Interaction) -> Result<(), ShowMeErrors> {
+    let line = serde_json::to_string(interaction)?;
+    let mut file = self
+      .file
+      .lock()
+      .map_err(|_| ShowMeErrors::SharedLocking("cassette".into()))?;
+    writeln!(file, "{line}")?;
+    Ok(file.flush()?)
+  }
+}
+
+/// Reads a cassette written by `CassetteWriter`: the header line, then one interaction per line.
+pub(crate) fn read_cassette(
+  path: &str,
+) -> Result<(CassetteHeader, Vec<Interaction>), ShowMeErrors> {
+  let mut lines = BufReader::new(File::open(path)?).lines();
+  let header = lines
+    .next()
+    .ok_or_else(|| ShowMeErrors::InvalidConfig(format!("{path} is an empty cassette")))??;
+
+  let interactions = lines
+    .filter(|line| line.as_ref().is_ok_and(|line| !line.trim().is_empty()))
+    .map(|line| Ok(serde_json::from_str(&line?)?))
+    .collect::<Result<Vec<_>, ShowMeErrors>>()?;
+
+  Ok((serde_json::from_str(&header)?, interactions))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SEARCH: &str = "/monitoring/logs?source=am-access&_queryFilter=%2Fpayload%2Fprincipal+eq+%22alice%22+and+%2Fpayload%2Flevel+eq+%22ERROR%22&beginTime=2024-05-01T00%3A00%3A00.000Z";
+
+  #[test]
+  fn blanks_redacted_fields_in_the_query_filter() {
+    let uri = redact_uri(SEARCH);
+
+    assert!(!uri.contains("alice"));
+    assert!(uri.contains("%22REDACTED%22"));
+    assert!(uri.contains("%22ERROR%22"));
+    assert!(uri.starts_with("/monitoring/logs?source=am-access&_queryFilter="));
+    assert!(uri.ends_with("&beginTime=2024-05-01T00%3A00%3A00.000Z"));
+    assert_eq!(redact_uri(&uri), uri);
+  }
+
+  #[test]
+  fn keeps_uris_without_redacted_literals_as_sent() {
+    for uri in [
+      "/am/json/realms/root/realms/alpha/realm-config/authentication/authenticationtrees/trees?_queryFilter=true",
+      "/monitoring/logs?source=am-core&transactionId=abc-123",
+      "/monitoring/logs?_queryFilter=%2Fpayload%2FtransactionId+eq+%22abc-123%22",
+      "/environment/sso/token",
+    ] {
+      assert_eq!(redact_uri(uri), uri);
+    }
+  }
+
+  #[test]
+  fn blanks_a_query_filter_that_does_not_parse() {
+    assert_eq!(
+      redact_uri("/monitoring/logs?_queryFilter=%2Fpayload%2Fprincipal+eq+alice"),
+      "/monitoring/logs?_queryFilter=REDACTED"
+    );
+  }
+
+  #[test]
+  fn replays_match_the_redacted_recording() {
+    let recorded = Interaction::new("GET", SEARCH, &[], 200, vec![], b"{}");
+    let live = SEARCH.replace("00%3A00%3A00", "01%3A00%3A00");
+
+    assert_eq!(
+      Interaction::key("GET", &recorded.uri, None),
+      Interaction::key("GET", SEARCH, None)
+    );
+    assert_eq!(
+      Interaction::timeless_key("GET", &recorded.uri, None),
+      Interaction::timeless_key("GET", &live, None)
+    );
+  }
+}
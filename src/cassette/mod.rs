@@ -0,0 +1,3 @@
+pub(crate) mod interaction;
+pub(crate) mod record;
+pub(crate) mod replay;
@@ -0,0 +1,150 @@
+use crate::cassette::interaction::{CassetteHeader, CassetteWriter, Interaction, kept_header};
+use crate::config::Config;
+use crate::errors::ShowMeErrors;
+use actix_web::http::StatusCode;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, rt, web};
+use chrono::Utc;
+use reqwest::{Client, Method};
+use std::net::TcpListener;
+use std::time::Duration;
+
+/// Request headers passed on to the tenant; the rest are the proxy's business.
+const FORWARDED_HEADERS: [&str; 6] = [
+  "authorization",
+  "x-api-key",
+  "x-api-secret",
+  "accept",
+  "accept-api-version",
+  "content-type",
+];
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Forwards the app's upstream calls to the real tenant and writes each exchange to a cassette.
+struct Recorder {
+  client: Client,
+  dom: String,
+  log_origin: String,
+  log_path: String,
+  cassette: CassetteWriter,
+}
+
+impl Recorder {
+  /// The logs API may live on another host than AM, so its paths go to its own origin.
+  fn upstream(&self, path: &str) -> &str {
+    if path.starts_with(&self.log_path) {
+      &self.log_origin
+    } else {
+      &self.dom
+    }
+  }
+
+  async fn forward(
+    &self,
+    req: &HttpRequest,
+    body: &[u8],
+  ) -> Result<(Interaction, HttpResponse), ShowMeErrors> {
+    let uri = req
+      .uri()
+      .path_and_query()
+      .map(|path| path.as_str())
+      .unwrap_or(req.path());
+    let method = Method::from_bytes(req.method().as_str().as_bytes())
+      .map_err(|_| ShowMeErrors::InvalidConfig(format!("unsupported method {}", req.method())))?;
+
+    let mut request = self
+      .client
+      .request(method, format!("{}{uri}", self.upstream(req.path())))
+      .body(body.to_vec());
+    for name in FORWARDED_HEADERS {
+      if let Some(value) = req.headers().get(name) {
+        request = request.header(name, value.as_bytes());
+      }
+    }
+
+    let response = request.send().await?;
+    let status = response.status().as_u16();
+    let headers: Vec<(String, String)> = response
+      .headers()
+      .iter()
+      .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+      .collect();
+    let response_body = response.bytes().await?;
+
+    let mut reply =
+      HttpResponse::build(StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY));
+    for (name, value) in headers.iter().filter(|(name, _)| kept_header(name)) {
+      reply.insert_header((name.as_str(), value.as_str()));
+    }
+
+    let interaction = Interaction::new(
+      req.method().as_str(),
+      uri,
+      body,
+      status,
+      headers,
+      &response_body,
+    );
+    Ok((interaction, reply.body(response_body)))
+  }
+}
+
+async fn record(req: HttpRequest, body: web::Bytes, recorder: web::Data<Recorder>) -> HttpResponse {
+  match recorder.forward(&req, &body).await {
+    Ok((interaction, response)) => {
+      if let Err(err) = recorder.cassette.append(&interaction) {
+        println!(
+          "Could not record {} {}: {}",
+          interaction.method, interaction.uri, err
+        );
+      }
+      response
+    }
+    Err(err) => {
+      println!("Could not forward {} {}: {}", req.method(), req.uri(), err);
+      HttpResponse::BadGateway().body(err.to_string())
+    }
+  }
+}
+
+/// Puts a recording proxy on `port` in front of the default environment and returns a config that
+/// sends that environment's traffic through it.
+pub(crate) async fn start_recording(
+  config: Config,
+  path: &str,
+  port: u16,
+) -> Result<Config, ShowMeErrors> {
+  let (name, environment) = config.into_default()?;
+  let (log_origin, log_path) = environment.log_api()?;
+
+  let cassette = CassetteWriter::create(
+    path,
+    &CassetteHeader {
+      environment: name.clone(),
+      log_path: log_path.clone(),
+      realms: environment.realms(),
+      recorded_at: Utc::now(),
+    },
+  )?;
+  let recorder = web::Data::new(Recorder {
+    client: Client::builder().timeout(FORWARD_TIMEOUT).build()?,
+    dom: environment.dom.trim_end_matches('/').to_string(),
+    log_origin,
+    log_path,
+    cassette,
+  });
+
+  let listener = TcpListener::bind(("127.0.0.1", port))?;
+  let url = format!("http://{}", listener.local_addr()?);
+  let server = HttpServer::new(move || {
+    App::new()
+      .app_data(recorder.clone())
+      .default_service(web::to(record))
+  })
+  .workers(1)
+  .listen(listener)?
+  .run();
+  rt::spawn(server);
+
+  println!("Recording [{name}] to {path} through {url}");
+  Ok(Config::single(&name, environment.proxied(&url)?))
+}
@@ -0,0 +1,135 @@
+use crate::cassette::interaction::{CassetteHeader, Interaction, read_cassette, recorded_body};
+use crate::errors::ShowMeErrors;
+use actix_web::http::StatusCode;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, rt, web};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::Mutex;
+
+/// What the replayed environment sends as its bearer token; the replay tenant doesn't check it.
+pub(crate) const REPLAY_TOKEN: &str = "replay";
+
+/// A running replay tenant: where it listens and what the cassette says was recorded.
+pub(crate) struct ReplayTenant {
+  pub(crate) url: String,
+  pub(crate) header: CassetteHeader,
+}
+
+struct Replayer {
+  /// Request key to the responses recorded for it, in the order they were given.
+  exact: HashMap<String, Vec<Interaction>>,
+  /// The same responses keyed without their time window.
+  timeless: HashMap<String, Vec<Interaction>>,
+  /// How many responses each key has served so far.
+  served: Mutex<HashMap<String, usize>>,
+}
+
+impl Replayer {
+  fn new(interactions: Vec<Interaction>) -> Self {
+    let mut exact: HashMap<String, Vec<Interaction>> = HashMap::new();
+    let mut timeless: HashMap<String, Vec<Interaction>> = HashMap::new();
+    for interaction in interactions {
+      let body = interaction.request_body.as_deref();
+      timeless
+        .entry(Interaction::timeless_key(
+          &interaction.method,
+          &interaction.uri,
+          body,
+        ))
+        .or_default()
+        .push(interaction.clone());
+      exact
+        .entry(Interaction::key(
+          &interaction.method,
+          &interaction.uri,
+          body,
+        ))
+        .or_default()
+        .push(interaction);
+    }
+
+    Replayer {
+      exact,
+      timeless,
+      served: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// The next recorded response to the request, repeating the last once they run out, so
+  /// background refreshes can go on asking.
+  fn next(&self, method: &str, uri: &str, body: Option<&str>) -> Option<Interaction> {
+    let exact = Interaction::key(method, uri, body);
+    let (key, recorded) = match self.exact.get(&exact) {
+      Some(recorded) => (exact, recorded),
+      None => {
+        let timeless = Interaction::timeless_key(method, uri, body);
+        let recorded = self.timeless.get(&timeless)?;
+        (timeless, recorded)
+      }
+    };
+
+    let mut served = self.served.lock().ok()?;
+    let count = served.entry(key).or_default();
+    let interaction = recorded.get(*count).or(recorded.last()).cloned();
+    *count += 1;
+    interaction
+  }
+}
+
+async fn replay(req: HttpRequest, body: web::Bytes, replayer: web::Data<Replayer>) -> HttpResponse {
+  let uri = req
+    .uri()
+    .path_and_query()
+    .map(|path| path.as_str())
+    .unwrap_or(req.path());
+  let method = req.method().as_str();
+  let request_body = recorded_body(uri, &body);
+
+  match replayer.next(method, uri, request_body.as_deref()) {
+    Some(interaction) => {
+      let mut reply = HttpResponse::build(
+        StatusCode::from_u16(interaction.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+      );
+      for (name, value) in &interaction.headers {
+        reply.insert_header((name.as_str(), value.as_str()));
+      }
+      reply.body(interaction.body)
+    }
+    None => {
+      println!("Nothing recorded for {method} {uri}");
+      HttpResponse::NotFound().json(json!({
+        "code": 404,
+        "reason": "Not Found",
+        "message": format!("Nothing recorded for {method} {uri}"),
+      }))
+    }
+  }
+}
+
+/// Starts a tenant on `port` (`0` for any free one) that answers from the cassette at `path`.
+pub(crate) async fn start_replay(path: &str, port: u16) -> Result<ReplayTenant, ShowMeErrors> {
+  let (header, interactions) = read_cassette(path)?;
+  println!(
+    "Replaying {} interactions recorded from [{}] at {}",
+    interactions.len(),
+    header.environment,
+    header.recorded_at
+  );
+  let replayer = web::Data::new(Replayer::new(interactions));
+
+  let listener = TcpListener::bind(("127.0.0.1", port))?;
+  let url = format!("http://{}", listener.local_addr()?);
+  let server = HttpServer::new(move || {
+    App::new()
+      .app_data(replayer.clone())
+      .default_service(web::to(replay))
+  })
+  .workers(1)
+  .listen(listener)?
+  .run();
+  rt::spawn(server);
+
+  println!("Replay tenant listening on {url}");
+  Ok(ReplayTenant { url, header })
+}
@@ -0,0 +1,287 @@
+use crate::cassette::replay::{REPLAY_TOKEN, ReplayTenant};
+use crate::errors::ShowMeErrors;
+use crate::mock::server::{LOG_KEY, LOG_SECRET, MockTenant, SERVICE_ACCOUNT_ID};
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+
+const DEFAULT_CONFIG_FILE: &str = "show-me-logs.toml";
+const DEFAULT_ENVIRONMENT: &str = "default";
+const MOCK_ENVIRONMENT: &str = "mock";
+/// Stand-in tenants don't check the logs API key pair; the app still needs one to send.
+const STAND_IN_LOG_KEY: &str = "stand-in";
+const DEFAULT_REALM: &str = "alpha";
+const DEFAULT_SCOPES: &str = "fr:idm:* fr:am:*";
+const TOKEN_PATH: &str = "am/oauth2/access_token";
+
+/// Where the service account's credentials come from.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum CredentialSource {
+  /// The JWK file the AIC admin console hands out for a service account.
+  JwkFile { path: String },
+  /// The same RSA key as a PEM encoded private key.
+  PemFile { path: String },
+  /// A JWK held in an env var, e.g. a CI secret.
+  JwkEnv { var: String },
+  /// A token used as-is and never exchanged, for stand-in AMs that don't do the JWT bearer grant.
+  Bearer { token: String },
+}
+
+/// One AIC tenant: the service account used for AM calls and the key pair for the logs API.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct EnvironmentConfig {
+  #[serde(default)]
+  pub(crate) sa_id: String,
+  pub(crate) dom: String,
+  /// Shorthand for a `jwk_file` credential source.
+  key_file: Option<String>,
+  credentials: Option<CredentialSource>,
+  scopes: Option<Vec<String>>,
+  /// The `aud` claim of the assertion, when the tenant doesn't answer on `dom`, e.g. behind a custom domain.
+  audience: Option<String>,
+  pub(crate) log_url: String,
+  pub(crate) log_key: String,
+  pub(crate) log_secret: String,
+  #[serde(default)]
+  realms: Vec<String>,
+}
+
+impl EnvironmentConfig {
+  /// The single environment described by the `SA_ID`, `DOM`, `SANDBOX`, `PING_KEY`, `PING_SEC`
+  /// and `REALMS` env vars, for running without a config file.
+  ///
+  /// Credentials come from the first of `BEARER_TOKEN`, `SA_JWK` (an inline JWK), `PEM_FILE` and
+  /// `KEY_FILE` that is set; `SCOPES` and `AUDIENCE` override the defaults.
+  fn from_env() -> Result<Self, ShowMeErrors> {
+    let var =
+      |name: &str| std::env::var(name).map_err(|_| ShowMeErrors::TokenDefault(name.to_string()));
+    let optional = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+    let credentials = if let Some(token) = optional("BEARER_TOKEN") {
+      CredentialSource::Bearer { token }
+    } else if optional("SA_JWK").is_some() {
+      CredentialSource::JwkEnv {
+        var: "SA_JWK".to_string(),
+      }
+    } else if let Some(path) = optional("PEM_FILE") {
+      CredentialSource::PemFile { path }
+    } else {
+      CredentialSource::JwkFile {
+        path: var("KEY_FILE")?,
+      }
+    };
+    let sa_id = match credentials {
+      CredentialSource::Bearer { .. } => optional("SA_ID").unwrap_or_default(),
+      _ => var("SA_ID")?,
+    };
+
+    Ok(EnvironmentConfig {
+      sa_id,
+      dom: var("DOM")?,
+      key_file: None,
+      credentials: Some(credentials),
+      scopes: optional("SCOPES")
+        .map(|scopes| scopes.split_whitespace().map(str::to_string).collect()),
+      audience: optional("AUDIENCE"),
+      log_url: var("SANDBOX")?,
+      log_key: var("PING_KEY")?,
+      log_secret: var("PING_SEC")?,
+      realms: std::env::var("REALMS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::to_string)
+        .collect(),
+    })
+  }
+
+  pub(crate) fn credentials(&self) -> Result<CredentialSource, ShowMeErrors> {
+    match (&self.credentials, &self.key_file) {
+      (Some(credentials), _) => Ok(credentials.clone()),
+      (None, Some(path)) => Ok(CredentialSource::JwkFile { path: path.clone() }),
+      (None, None) => Err(ShowMeErrors::InvalidConfig(format!(
+        "no credentials or key_file for {}",
+        self.dom
+      ))),
+    }
+  }
+
+  /// Space separated, as the token endpoint expects them.
+  pub(crate) fn scope(&self) -> String {
+    match &self.scopes {
+      Some(scopes) => scopes.join(" "),
+      None => DEFAULT_SCOPES.to_string(),
+    }
+  }
+
+  pub(crate) fn token_url(&self) -> String {
+    format!("{}/{TOKEN_PATH}", self.dom)
+  }
+
+  pub(crate) fn audience(&self) -> String {
+    self.audience.clone().unwrap_or_else(|| self.token_url())
+  }
+
+  /// The origin and path of the logs API, e.g. `https://tenant.id.forgerock.io` and `/monitoring/logs`.
+  pub(crate) fn log_api(&self) -> Result<(String, String), ShowMeErrors> {
+    let url = Url::parse(&self.log_url)
+      .map_err(|err| ShowMeErrors::InvalidConfig(format!("log_url {}: {err}", self.log_url)))?;
+    Ok((
+      url.origin().ascii_serialization(),
+      url.path().trim_end_matches('/').to_string(),
+    ))
+  }
+
+  /// The same tenant reached through a proxy at `url` that answers for both AM and the logs API.
+  /// The assertion audience stays the tenant's, since that is who checks it.
+  pub(crate) fn proxied(&self, url: &str) -> Result<Self, ShowMeErrors> {
+    let (_, log_path) = self.log_api()?;
+    Ok(EnvironmentConfig {
+      dom: url.to_string(),
+      log_url: format!("{url}{log_path}"),
+      audience: Some(self.audience()),
+      ..self.clone()
+    })
+  }
+
+  /// Realms to load, defaulting to `alpha` when none are listed.
+  pub(crate) fn realms(&self) -> Vec<String> {
+    let realms: Vec<String> = self
+      .realms
+      .iter()
+      .map(|realm| realm.trim().trim_matches('/').to_string())
+      .filter(|realm| !realm.is_empty())
+      .collect();
+
+    if realms.is_empty() {
+      vec![DEFAULT_REALM.to_string()]
+    } else {
+      realms
+    }
+  }
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Config {
+  default: Option<String>,
+  pub(crate) environments: BTreeMap<String, EnvironmentConfig>,
+}
+
+impl Config {
+  /// Reads the TOML file named by `CONFIG_FILE` (or `show-me-logs.toml`), falling back to a single
+  /// environment built from the env vars when there is no file. A `CONFIG_FILE` that can't be
+  /// read is an error rather than a silent fallback.
+  pub(crate) fn load() -> Result<Self, ShowMeErrors> {
+    let explicit = std::env::var("CONFIG_FILE").ok();
+    let path = explicit.clone().unwrap_or(DEFAULT_CONFIG_FILE.to_string());
+
+    let config: Config = match fs::read_to_string(&path) {
+      Ok(text) => {
+        toml::from_str(&text).map_err(|err| ShowMeErrors::InvalidConfig(err.to_string()))?
+      }
+      Err(err) if explicit.is_some() => {
+        return Err(ShowMeErrors::InvalidConfig(format!(
+          "CONFIG_FILE {path} can't be read: {err}"
+        )));
+      }
+      Err(_) => Config {
+        default: None,
+        environments: BTreeMap::from([(
+          DEFAULT_ENVIRONMENT.to_string(),
+          EnvironmentConfig::from_env()?,
+        )]),
+      },
+    };
+
+    if config.environments.is_empty() {
+      return Err(ShowMeErrors::InvalidConfig(format!(
+        "{path} has no environments"
+      )));
+    }
+    if let Some(default) = &config.default
+      && !config.environments.contains_key(default)
+    {
+      return Err(ShowMeErrors::InvalidConfig(format!(
+        "default environment [{default}] is not defined"
+      )));
+    }
+    Ok(config)
+  }
+
+  pub(crate) fn single(name: &str, environment: EnvironmentConfig) -> Self {
+    Config {
+      default: None,
+      environments: BTreeMap::from([(name.to_string(), environment)]),
+    }
+  }
+
+  /// Just the default environment, for modes that serve a single one.
+  pub(crate) fn into_default(mut self) -> Result<(String, EnvironmentConfig), ShowMeErrors> {
+    let name = self
+      .default_environment()
+      .ok_or_else(|| ShowMeErrors::InvalidConfig("no environments".into()))?
+      .to_string();
+    let environment = self
+      .environments
+      .remove(&name)
+      .ok_or_else(|| ShowMeErrors::InvalidConfig(format!("no environment [{name}]")))?;
+    Ok((name, environment))
+  }
+
+  /// A single environment backed by the mock tenant, signing in with the key it generated.
+  pub(crate) fn mock(tenant: &MockTenant) -> Self {
+    let environment = EnvironmentConfig {
+      sa_id: SERVICE_ACCOUNT_ID.to_string(),
+      dom: tenant.url.clone(),
+      key_file: None,
+      credentials: Some(CredentialSource::PemFile {
+        path: tenant.key_file.display().to_string(),
+      }),
+      scopes: None,
+      audience: None,
+      log_url: format!("{}/monitoring/logs", tenant.url),
+      log_key: LOG_KEY.to_string(),
+      log_secret: LOG_SECRET.to_string(),
+      realms: tenant.realms.clone(),
+    };
+    Config::single(MOCK_ENVIRONMENT, environment)
+  }
+
+  /// The recorded environment, served by a tenant replaying its cassette.
+  pub(crate) fn replay(tenant: &ReplayTenant) -> Self {
+    let environment = EnvironmentConfig {
+      sa_id: String::new(),
+      dom: tenant.url.clone(),
+      key_file: None,
+      credentials: Some(CredentialSource::Bearer {
+        token: REPLAY_TOKEN.to_string(),
+      }),
+      scopes: None,
+      audience: None,
+      log_url: format!("{}{}", tenant.url, tenant.header.log_path),
+      log_key: STAND_IN_LOG_KEY.to_string(),
+      log_secret: STAND_IN_LOG_KEY.to_string(),
+      realms: tenant.header.realms.clone(),
+    };
+    Config::single(&tenant.header.environment, environment)
+  }
+
+  /// The environment behind the unprefixed `/api` routes: `default`, else the first by name.
+  pub(crate) fn default_environment(&self) -> Option<&str> {
+    self
+      .default
+      .as_deref()
+      .or_else(|| self.environments.keys().next().map(String::as_str))
+  }
+}
+
+/// A port from the env var `name`, or `default` when it isn't set.
+pub(crate) fn port(name: &str, default: u16) -> Result<u16, ShowMeErrors> {
+  match std::env::var(name) {
+    Ok(port) => port
+      .parse()
+      .map_err(|_| ShowMeErrors::InvalidConfig(format!("{name} is not a port: {port}"))),
+    Err(_) => Ok(default),
+  }
+}
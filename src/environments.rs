@@ -0,0 +1,114 @@
+use crate::AppMutState;
+use crate::config::EnvironmentConfig;
+use crate::errors::ShowMeErrors;
+use actix_web::{get, web};
+use futures::future::join_all;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Every configured environment, keyed by its name in the config file: those that connected at
+/// startup and are served, and those that didn't with the reason why.
+pub(crate) struct Environments {
+  pub(crate) default: String,
+  pub(crate) states: BTreeMap<String, web::Data<AppMutState>>,
+  pub(crate) failed: BTreeMap<String, FailedEnvironment>,
+}
+
+/// An environment that could not connect at startup, reported as unhealthy but not served.
+pub(crate) struct FailedEnvironment {
+  pub(crate) config: EnvironmentConfig,
+  pub(crate) error: String,
+}
+
+#[derive(Serialize)]
+struct EnvironmentHealth {
+  name: String,
+  dom: String,
+  default: bool,
+  realms: Vec<String>,
+  trees: usize,
+  token: bool,
+  logs: bool,
+  errors: Vec<String>,
+}
+
+/// Checks that the environment can still mint an AM token and read the logs API.
+async fn check_health(name: &str, data: &AppMutState, default: bool) -> EnvironmentHealth {
+  let mut errors = vec![];
+
+  let token = data
+    .token
+    .access_token()
+    .await
+    .map(|token_str| !token_str.is_empty())
+    .unwrap_or_else(|err| {
+      errors.push(format!("token: {err}"));
+      false
+    });
+
+  let logs = data
+    .log_client
+    .check()
+    .await
+    .map(|_| true)
+    .unwrap_or_else(|err| {
+      errors.push(format!("logs: {err}"));
+      false
+    });
+
+  let trees = data
+    .authentication_tree
+    .read()
+    .map(|trees| trees.values().map(|list| list.get_tree_list().len()).sum())
+    .unwrap_or_default();
+
+  EnvironmentHealth {
+    name: name.to_string(),
+    dom: data.token.dom.clone(),
+    default,
+    realms: data.realms.clone(),
+    trees,
+    token,
+    logs,
+    errors,
+  }
+}
+
+fn failed_health(name: &str, failed: &FailedEnvironment) -> EnvironmentHealth {
+  EnvironmentHealth {
+    name: name.to_string(),
+    dom: failed.config.dom.clone(),
+    default: false,
+    realms: failed.config.realms(),
+    trees: 0,
+    token: false,
+    logs: false,
+    errors: vec![format!("connect: {}", failed.error)],
+  }
+}
+
+#[get("")]
+async fn list_environments(
+  environments: web::Data<Environments>,
+) -> Result<web::Json<Vec<EnvironmentHealth>>, ShowMeErrors> {
+  let mut health = join_all(
+    environments
+      .states
+      .iter()
+      .map(|(name, data)| check_health(name, data, name == &environments.default)),
+  )
+  .await;
+  health.extend(
+    environments
+      .failed
+      .iter()
+      .map(|(name, failed)| failed_health(name, failed)),
+  );
+  health.sort_by(|a, b| a.name.cmp(&b.name));
+
+  Ok(web::Json(health))
+}
+
+pub fn environments_api(cfg: &mut web::ServiceConfig) {
+  cfg.service(list_environments);
+}
@@ -17,8 +17,32 @@ pub enum ShowMeErrors {
   SharedLocking(String),
   #[error("There are no logs for id: [{0}].")]
   NoLogsFound(String),
+  #[error("Invalid configuration: [{0}].")]
+  InvalidConfig(String),
+  #[error("Ping API rate limit exhausted: [{0}].")]
+  RateLimited(String),
+  #[error("Realm [{0}] is not configured.")]
+  UnknownRealm(String),
+  #[error("There is no revision history for: [{0}].")]
+  NoRevisionFound(String),
+  #[error("There is no journey named: [{0}].")]
+  NoJourneyFound(String),
+  #[error("There is no watch with id: [{0}].")]
+  NoWatchFound(String),
+  #[error("Invalid watch: [{0}].")]
+  InvalidWatch(String),
+  #[error("Invalid query filter: [{0}].")]
+  InvalidQueryFilter(String),
+  #[error("Invalid time window: [{0}].")]
+  InvalidTimeWindow(String),
+  #[error("Invalid metric filter: [{0}].")]
+  InvalidMetricFilter(String),
+  #[error("Unreadable prometheus metrics: [{0}].")]
+  InvalidMetrics(String),
   #[error("Creation of the api token failed: [{0}].")]
   TokenDefault(String),
+  #[error("The access token exchange failed: [{0}].")]
+  TokenExchange(String),
   #[error("Failed to create openssl rand but")]
   TokenOpenSsl(#[from] openssl::error::ErrorStack),
   #[error("Failed to create openssl rand but")]
@@ -40,7 +64,19 @@ impl error::ResponseError for ShowMeErrors {
       ShowMeErrors::Parsing(_) => StatusCode::INTERNAL_SERVER_ERROR,
       ShowMeErrors::SharedLocking(_) => StatusCode::INTERNAL_SERVER_ERROR,
       ShowMeErrors::NoLogsFound(_) => StatusCode::NOT_FOUND,
+      ShowMeErrors::InvalidConfig(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      ShowMeErrors::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+      ShowMeErrors::UnknownRealm(_) => StatusCode::NOT_FOUND,
+      ShowMeErrors::NoRevisionFound(_) => StatusCode::NOT_FOUND,
+      ShowMeErrors::NoJourneyFound(_) => StatusCode::NOT_FOUND,
+      ShowMeErrors::NoWatchFound(_) => StatusCode::NOT_FOUND,
+      ShowMeErrors::InvalidWatch(_) => StatusCode::BAD_REQUEST,
+      ShowMeErrors::InvalidQueryFilter(_) => StatusCode::BAD_REQUEST,
+      ShowMeErrors::InvalidTimeWindow(_) => StatusCode::BAD_REQUEST,
+      ShowMeErrors::InvalidMetricFilter(_) => StatusCode::BAD_REQUEST,
+      ShowMeErrors::InvalidMetrics(_) => StatusCode::BAD_GATEWAY,
       ShowMeErrors::TokenDefault(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      ShowMeErrors::TokenExchange(_) => StatusCode::BAD_GATEWAY,
       _ => StatusCode::INTERNAL_SERVER_ERROR,
     }
   }
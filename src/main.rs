@@ -1,35 +1,97 @@
+use crate::cassette::record::start_recording;
+use crate::cassette::replay::start_replay;
+use crate::config::{Config, EnvironmentConfig, port};
+use crate::environments::{Environments, FailedEnvironment, environments_api};
 use crate::errors::ShowMeErrors;
+use crate::mock::server::start_mock_tenant;
+use crate::monitoring::scrapes::Scrapes;
+use crate::monitoring::service::monitoring_api;
+use crate::ping_logs::client::LogClient;
 use crate::ping_logs::service::log_api;
-use crate::token::{Token, get_usable_token};
-use crate::trees::journeys::AuthenticationTreeList;
+use crate::ping_logs::tracking_cache::TrackingCache;
+use crate::ping_logs::watchers::{TailSubscribers, WatchRegistry, tail_watches};
+use crate::token::{TokenManager, refresh_tokens};
+use crate::realms::{Realm, realms_api};
+use crate::trees::history::TreeHistory;
+use crate::trees::journeys::{AuthenticationTreeList, get_authentication_trees};
+use crate::trees::refresh::{TreeEvents, refresh_trees};
 use crate::trees::service::trees_api;
 use crate::workers::scripts::{RichScript, ScriptConfig,  list_scripts, get_rich_script};
 use actix_web::http::header::ContentType;
 use actix_web::rt::time::sleep;
-use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, get, mime, rt, web};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, mime, rt, web};
 use futures_util::StreamExt as _;
-use reqwest::Client;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
+mod cassette;
+mod config;
+mod environments;
 mod errors;
+mod mock;
+mod monitoring;
 mod ping_logs;
+mod realms;
 mod token;
 mod trees;
 mod workers;
 
 struct AppMutState {
-  transaction_id: Mutex<String>,
-  authentication_tree: AuthenticationTreeList,
-  token: Token,
-  token_str: Mutex<String>,
-  payload: Mutex<token::Payload>,
-  sec: String,
-  key: String,
-  log: String,
-  script_config: Mutex<HashMap<String, ScriptConfig>>,
+  watches: Mutex<WatchRegistry>,
+  tail_subscribers: Mutex<TailSubscribers>,
+  tracking_cache: Mutex<TrackingCache>,
+  realms: Vec<String>,
+  authentication_tree: RwLock<HashMap<String, Arc<AuthenticationTreeList>>>,
+  tree_events: Mutex<TreeEvents>,
+  tree_history: Mutex<TreeHistory>,
+  token: TokenManager,
+  log_client: LogClient,
+  script_config: Mutex<HashMap<String, HashMap<String, ScriptConfig>>>,
+  scrapes: Scrapes,
+}
+
+impl AppMutState {
+  /// Logs in to one environment and loads the trees of each of its realms.
+  async fn connect(name: &str, config: &EnvironmentConfig) -> Result<Self, ShowMeErrors> {
+    let log_client = LogClient::new(config)?;
+    let token = TokenManager::new(config, log_client.clone())?;
+    let token_str = token.access_token().await?;
+
+    let realms = config.realms();
+    let mut authentication_tree = HashMap::new();
+    for realm in &realms {
+      authentication_tree.insert(
+        realm.clone(),
+        Arc::new(get_authentication_trees(&log_client, &token.dom, &token_str, realm).await?),
+      );
+    }
+
+    Ok(AppMutState {
+      watches: Mutex::new(WatchRegistry::default()),
+      tail_subscribers: Mutex::new(TailSubscribers::default()),
+      tracking_cache: Mutex::new(TrackingCache::default()),
+      realms,
+      authentication_tree: RwLock::new(authentication_tree),
+      tree_events: Mutex::new(TreeEvents::default()),
+      tree_history: Mutex::new(TreeHistory::load(name)),
+      token,
+      log_client,
+      script_config: Mutex::new(HashMap::new()),
+      scrapes: Scrapes::default(),
+    })
+  }
+
+  fn realm_trees(&self, Realm(realm): &Realm) -> Result<Arc<AuthenticationTreeList>, ShowMeErrors> {
+    self
+      .authentication_tree
+      .read()
+      .map_err(|_| ShowMeErrors::SharedLocking("authentication trees".into()))?
+      .get(realm)
+      .cloned()
+      .ok_or_else(|| ShowMeErrors::UnknownRealm(realm.clone()))
+  }
 }
 // this could be done with rust embed
 async fn index(req: HttpRequest) -> Result<HttpResponse, ShowMeErrors> {
@@ -65,108 +127,173 @@ async fn index(req: HttpRequest) -> Result<HttpResponse, ShowMeErrors> {
   })
 }
 
-#[get("/idm")]
-async fn idm(data: web::Data<AppMutState>) -> Result<String, ShowMeErrors> {
-  let client = Client::new();
-  let metrics = &*client
-    .get(format!("{}/monitoring/prometheus/idm", &data.token.dom,))
-    .header("x-api-key", &data.key)
-    .header("x-api-secret", &data.sec)
-    .send()
-    .await?
-    .text()
-    .await?;
-
-  Ok(metrics.to_string())
-}
-
-#[get("/am")]
-async fn am(data: web::Data<AppMutState>) -> Result<String, ShowMeErrors> {
-  let client = Client::new();
-  let metrics = client
-    .get(format!("{}/monitoring/prometheus/am", &data.token.dom,))
-    .header("x-api-key", &data.key)
-    .header("x-api-secret", &data.sec)
-    .send()
-    .await?
-    .text()
-    .await?;
-
-  Ok(metrics.to_string())
-}
-
 #[derive(Debug)]
 struct NodeOutcomeEdge {
   name: String,
   outcome: String,
+  timestamp: chrono::DateTime<chrono::Utc>,
+  node_id: Option<String>,
+  tree_name: Option<String>,
 }
 
-#[actix_web::main]
-async fn main() -> Result<(), ShowMeErrors> {
-  let (token, payload_init) = Token::new().await?;
-  let payload_mux_init = Mutex::new(payload_init);
-
-  let client = Client::new();
-  let token_mux = Mutex::new("".to_string());
-
-  // ToDo - If this was a Arc<Mutex> it would not need the be &mut
-  let (token_str, payload_up) = get_usable_token(&token, &payload_mux_init, &token_mux).await?;
-
-  let authentication_tree: AuthenticationTreeList = serde_json::from_slice(&client.get(format!("{}/am/json/realms/root/realms/alpha/realm-config/authentication/authenticationtrees/trees?_queryFilter=true", token.dom, )).header("authorization", format!("Bearer {}", token_str)).send().await?.bytes().await?)?;
-
-  let url = std::env::var("SANDBOX")?;
-  let key = std::env::var("PING_KEY")?;
-  let sec = std::env::var("PING_SEC")?;
-  let state = web::Data::new(AppMutState {
-    transaction_id: Mutex::new(String::new()),
-    authentication_tree,
-    token,
-    token_str: token_mux,
-    payload: Mutex::new(payload_up),
-    sec,
-    key,
-    log: url,
-    script_config: Mutex::new(HashMap::new()),
-  });
+/// Re-reads each realm's script list, keeping the previous one for a realm that can't be read.
+async fn refresh_scripts(data: &AppMutState) -> Result<(), ShowMeErrors> {
+  let token_str = data.token.access_token().await?;
+  for realm in &data.realms {
+    match list_scripts(&data.log_client, &data.token.dom, &token_str, realm).await {
+      Ok(scripts) => {
+        data
+          .script_config
+          .lock()
+          .map_err(|_| ShowMeErrors::SharedLocking("script list".into()))?
+          .insert(realm.clone(), scripts);
+      }
+      Err(err) => println!("Could not refresh scripts for realm [{}]: {}", realm, err),
+    }
+  }
+  Ok(())
+}
 
+/// Keeps an environment's script list fresh and runs its watch tails and tree refreshes.
+fn spawn_workers(state: web::Data<AppMutState>) {
   let data = state.clone();
   rt::spawn(async move {
-    let client = Client::new();
     loop {
-      let (token_str, payload) =
-        get_usable_token(&data.token, &data.payload, &data.token_str).await?;
-      let scripts = list_scripts(&client, &data.token.dom, &token_str).await?;
+      if let Err(err) = refresh_scripts(&data).await {
+        println!("Could not refresh the script list: {}", err);
+      }
+      sleep(Duration::from_secs(30)).await;
+    }
+  });
 
-      let mut sct = data
-        .script_config
-        .lock()
-        .map_err(|_| ShowMeErrors::SharedLocking("script list".into()))?;
+  rt::spawn(refresh_tokens(state.clone()));
+  rt::spawn(tail_watches(state.clone()));
+  rt::spawn(refresh_trees(state));
+}
 
-      *sct = scripts;
+/// The routes served for each environment, both under `/api/env/{env}` and, for the default
+/// environment, straight under `/api`.
+fn environment_api(cfg: &mut web::ServiceConfig) {
+  cfg
+    .configure(trees_api)
+    .configure(log_api)
+    .service(
+      web::scope("/realms")
+        .configure(realms_api)
+        .service(web::scope("/{realm}").configure(trees_api)),
+    )
+    .configure(monitoring_api);
+}
 
-      // Otherwise this lock would only go out of scope when the sleep endds.
-      drop(sct);
+const DEFAULT_PORT: u16 = 8081;
+const DEFAULT_MOCK_PORT: u16 = 8082;
+const DEFAULT_CASSETTE_PORT: u16 = 8083;
 
-      sleep(Duration::from_secs(30)).await;
+/// The value following `flag` on the command line, e.g. the path in `--record session.jsonl`.
+fn arg_value(flag: &str) -> Result<Option<String>, ShowMeErrors> {
+  let mut args = std::env::args().skip_while(|arg| arg != flag);
+  if args.next().is_none() {
+    return Ok(None);
+  }
+  match args.next() {
+    Some(value) if !value.starts_with("--") => Ok(Some(value)),
+    _ => Err(ShowMeErrors::InvalidConfig(format!("{flag} needs a cassette path"))),
+  }
+}
+
+#[actix_web::main]
+async fn main() -> Result<(), ShowMeErrors> {
+  // `--mock` serves the app against a built-in stand-in tenant instead of the configured ones.
+  let mock_tenant = if std::env::args().any(|arg| arg == "--mock") {
+    Some(start_mock_tenant(port("MOCK_PORT", DEFAULT_MOCK_PORT)?).await?)
+  } else {
+    None
+  };
+  // `--record <path>` proxies the default environment and keeps its traffic in a cassette, which
+  // `--replay <path>` then serves the app from in place of any tenant.
+  let (record, replay) = (arg_value("--record")?, arg_value("--replay")?);
+  let cassette_port = port("CASSETTE_PORT", DEFAULT_CASSETTE_PORT)?;
+  let config = match (record, replay) {
+    (Some(_), Some(_)) => {
+      return Err(ShowMeErrors::InvalidConfig(
+        "--record and --replay can't be used together".into(),
+      ));
     }
-    Ok::<(), ShowMeErrors>(())
-  });
+    (None, Some(path)) => Config::replay(&start_replay(&path, cassette_port).await?),
+    (record, None) => {
+      let config = match &mock_tenant {
+        Some(tenant) => Config::mock(tenant),
+        None => Config::load()?,
+      };
+      match record {
+        Some(path) => start_recording(config, &path, cassette_port).await?,
+        None => config,
+      }
+    }
+  };
 
+  let mut states = BTreeMap::new();
+  let mut failed = BTreeMap::new();
+  for (name, environment) in &config.environments {
+    match AppMutState::connect(name, environment).await {
+      Ok(state) => {
+        let state = web::Data::new(state);
+        spawn_workers(state.clone());
+        states.insert(name.clone(), state);
+      }
+      Err(err) => {
+        println!("Could not connect to environment [{}]: {}", name, err);
+        failed.insert(
+          name.clone(),
+          FailedEnvironment {
+            config: environment.clone(),
+            error: err.to_string(),
+          },
+        );
+      }
+    }
+  }
+  // Connected environments keep their signing keys in memory.
+  drop(mock_tenant);
+
+  let default = config
+    .default_environment()
+    .filter(|name| states.contains_key(*name))
+    .or_else(|| states.keys().next().map(String::as_str))
+    .ok_or_else(|| ShowMeErrors::InvalidConfig("no environment could connect".into()))?
+    .to_string();
+  let default_state = states[&default].clone();
+  let environments = web::Data::new(Environments {
+    default,
+    states,
+    failed,
+  });
 
   HttpServer::new(move || {
     let cors = actix_cors::Cors::permissive().allow_any_header();
+    let per_environment = environments.states.iter().fold(
+      web::scope("/env"),
+      |scope, (name, state)| {
+        scope.service(
+          web::scope(&format!("/{name}"))
+            .app_data(state.clone())
+            .configure(environment_api),
+        )
+      },
+    );
+
     App::new()
-      .app_data(state.clone())
+      .app_data(default_state.clone())
+      .app_data(environments.clone())
       .wrap(cors)
       .service(
         web::scope("/api")
-          .configure(trees_api)
-          .configure(log_api)
-          .service(web::scope("/monitoring").service(am).service(idm)),
+          .service(per_environment.configure(environments_api))
+          .configure(environment_api),
       )
       .route("/{filename:.*}", web::get().to(index))
   })
-  .bind(("0.0.0.0", 8081))?
+  .bind(("0.0.0.0", port("PORT", DEFAULT_PORT)?))?
   .run()
   .await?;
   Ok(())
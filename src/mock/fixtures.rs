@@ -0,0 +1,107 @@
+use crate::errors::ShowMeErrors;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The files a fixture directory may hold, built into the binary so `--mock` works from anywhere.
+const EMBEDDED: [(&str, &str); 8] = [
+  ("trees.json", include_str!("../../fixtures/mock/trees.json")),
+  ("nodes.json", include_str!("../../fixtures/mock/nodes.json")),
+  (
+    "scripts.json",
+    include_str!("../../fixtures/mock/scripts.json"),
+  ),
+  (
+    "script_schema.json",
+    include_str!("../../fixtures/mock/script_schema.json"),
+  ),
+  ("logs.json", include_str!("../../fixtures/mock/logs.json")),
+  (
+    "sources.json",
+    include_str!("../../fixtures/mock/sources.json"),
+  ),
+  (
+    "prometheus/am.txt",
+    include_str!("../../fixtures/mock/prometheus/am.txt"),
+  ),
+  (
+    "prometheus/idm.txt",
+    include_str!("../../fixtures/mock/prometheus/idm.txt"),
+  ),
+];
+
+#[derive(Deserialize)]
+struct Sources {
+  result: Vec<String>,
+}
+
+/// What the mock tenant serves, in the shapes AM and the monitoring API return them.
+pub(crate) struct Fixtures {
+  /// Realm to the trees in it.
+  pub(crate) trees: HashMap<String, Vec<Value>>,
+  /// Node id to node config, whatever the node type.
+  pub(crate) nodes: HashMap<String, Value>,
+  pub(crate) scripts: HashMap<String, Value>,
+  /// The `ScriptedDecisionNode` schema, whose script enum is how the crate lists scripts.
+  pub(crate) script_schema: Value,
+  /// Every log record across all sources, oldest first.
+  pub(crate) logs: Vec<Value>,
+  pub(crate) sources: Vec<String>,
+  /// `am` and `idm` to their exposition text.
+  pub(crate) prometheus: HashMap<String, String>,
+}
+
+/// A fixture from `dir` when it has one by that name, else the built-in one.
+fn read(dir: Option<&Path>, name: &str) -> Result<String, ShowMeErrors> {
+  if let Some(path) = dir.map(|dir| dir.join(name))
+    && path.exists()
+  {
+    return Ok(fs::read_to_string(path)?);
+  }
+
+  EMBEDDED
+    .iter()
+    .find(|(file, _)| *file == name)
+    .map(|(_, text)| text.to_string())
+    .ok_or_else(|| ShowMeErrors::InvalidConfig(format!("no mock fixture named {name}")))
+}
+
+/// When a log record was written, `None` if it has no parsable `timestamp`.
+pub(crate) fn timestamp(record: &Value) -> Option<DateTime<Utc>> {
+  record.get("timestamp")?.as_str()?.parse().ok()
+}
+
+impl Fixtures {
+  /// The built-in fixtures, with any file in the `MOCK_FIXTURES` directory replacing its namesake.
+  pub(crate) fn load() -> Result<Self, ShowMeErrors> {
+    let dir = std::env::var("MOCK_FIXTURES").ok().map(PathBuf::from);
+    let dir = dir.as_deref();
+
+    let mut logs: Vec<Value> = serde_json::from_str(&read(dir, "logs.json")?)?;
+    logs.sort_by_key(timestamp);
+    let sources: Sources = serde_json::from_str(&read(dir, "sources.json")?)?;
+
+    Ok(Fixtures {
+      trees: serde_json::from_str(&read(dir, "trees.json")?)?,
+      nodes: serde_json::from_str(&read(dir, "nodes.json")?)?,
+      scripts: serde_json::from_str(&read(dir, "scripts.json")?)?,
+      script_schema: serde_json::from_str(&read(dir, "script_schema.json")?)?,
+      logs,
+      sources: sources.result,
+      prometheus: HashMap::from([
+        ("am".to_string(), read(dir, "prometheus/am.txt")?),
+        ("idm".to_string(), read(dir, "prometheus/idm.txt")?),
+      ]),
+    })
+  }
+
+  /// The realms with trees, sorted so the mock environment's default realm is stable.
+  pub(crate) fn realms(&self) -> Vec<String> {
+    let mut realms: Vec<String> = self.trees.keys().cloned().collect();
+    realms.sort();
+    realms
+  }
+}
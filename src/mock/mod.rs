@@ -0,0 +1,2 @@
+pub(crate) mod fixtures;
+pub(crate) mod server;
@@ -0,0 +1,539 @@
+use crate::errors::ShowMeErrors;
+use crate::mock::fixtures::{Fixtures, timestamp};
+use crate::ping_logs::query_filter::QueryFilter;
+use crate::ping_logs::window::MAX_WINDOW_HOURS;
+use actix_web::http::StatusCode;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, get, post, rt, web};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use openssl::rsa::Rsa;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub(crate) const SERVICE_ACCOUNT_ID: &str = "mock-service-account";
+pub(crate) const LOG_KEY: &str = "mock-log-key";
+pub(crate) const LOG_SECRET: &str = "mock-log-secret";
+const CLIENT_ID: &str = "service-account";
+const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+const ACCESS_TOKEN_PREFIX: &str = "mock-access-token-";
+const ACCESS_TOKEN_LIFETIME_SECS: i64 = 899;
+/// Far below the real API's page size, so a handful of fixture records already spans pages.
+const PAGE_SIZE: usize = 3;
+
+/// A running mock tenant: where it listens, the realms it has trees for and the key its
+/// service account signs assertions with.
+pub(crate) struct MockTenant {
+  pub(crate) url: String,
+  pub(crate) realms: Vec<String>,
+  pub(crate) key_file: PathBuf,
+}
+
+impl Drop for MockTenant {
+  /// The key is only read while environments connect, so it needn't outlive startup.
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.key_file);
+  }
+}
+
+struct MockState {
+  fixtures: Fixtures,
+  /// The public half of the service account key, to check assertions with.
+  public_key: DecodingKey,
+  token_url: String,
+  issued: AtomicUsize,
+}
+
+/// Starts a stand-in AIC tenant on `port` (`0` for any free one) serving the fixtures, and writes
+/// a freshly generated service account key next to it.
+pub(crate) async fn start_mock_tenant(port: u16) -> Result<MockTenant, ShowMeErrors> {
+  let fixtures = Fixtures::load()?;
+  let realms = fixtures.realms();
+
+  let rsa = Rsa::generate(2048)?;
+  let key_file = std::env::temp_dir().join(format!("show-me-logs-mock-{}.pem", std::process::id()));
+  fs::write(&key_file, rsa.private_key_to_pem()?)?;
+
+  let listener = TcpListener::bind(("127.0.0.1", port))?;
+  let url = format!("http://{}", listener.local_addr()?);
+  let state = web::Data::new(MockState {
+    fixtures,
+    public_key: DecodingKey::from_rsa_pem(&rsa.public_key_to_pem()?)?,
+    token_url: format!("{url}/am/oauth2/access_token"),
+    issued: AtomicUsize::new(0),
+  });
+
+  let server = HttpServer::new(move || App::new().app_data(state.clone()).configure(mock_api))
+    .workers(1)
+    .listen(listener)?
+    .run();
+  rt::spawn(server);
+
+  println!("Mock tenant listening on {url}");
+  Ok(MockTenant {
+    url,
+    realms,
+    key_file,
+  })
+}
+
+/// An error body shaped like AM's, e.g. `{"code":404,"reason":"Not Found","message":"..."}`.
+fn am_error(status: StatusCode, message: &str) -> HttpResponse {
+  HttpResponse::build(status).json(json!({
+    "code": status.as_u16(),
+    "reason": status.canonical_reason().unwrap_or_default(),
+    "message": message,
+  }))
+}
+
+/// Whether the request carries an access token this mock issued.
+fn has_access_token(req: &HttpRequest) -> bool {
+  req
+    .headers()
+    .get("authorization")
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "))
+    .is_some_and(|token| token.starts_with(ACCESS_TOKEN_PREFIX))
+}
+
+/// Rejects requests without an access token this mock issued, or for a realm with no fixtures.
+fn check_am_request(req: &HttpRequest, state: &MockState, realm: &str) -> Result<(), HttpResponse> {
+  if !has_access_token(req) {
+    return Err(am_error(StatusCode::UNAUTHORIZED, "Access Denied"));
+  }
+  if !state.fixtures.trees.contains_key(realm) {
+    return Err(am_error(StatusCode::NOT_FOUND, "Realm not found"));
+  }
+  Ok(())
+}
+
+/// Whether the request carries the logs API key pair, which the prometheus endpoints take too.
+fn has_api_key(req: &HttpRequest) -> bool {
+  let header = |name: &str| {
+    req
+      .headers()
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+  };
+  header("x-api-key") == Some(LOG_KEY) && header("x-api-secret") == Some(LOG_SECRET)
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+  client_id: String,
+  grant_type: String,
+  assertion: String,
+  scope: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AssertionClaims {
+  sub: String,
+}
+
+/// Checks the assertion the way AM does, signature, `aud`, `iss` and `exp` included.
+#[post("/am/oauth2/access_token")]
+async fn access_token(form: web::Form<TokenRequest>, state: web::Data<MockState>) -> HttpResponse {
+  let invalid = |error: &str, description: String| {
+    HttpResponse::BadRequest().json(json!({ "error": error, "error_description": description }))
+  };
+
+  if form.grant_type != GRANT_TYPE {
+    return invalid("unsupported_grant_type", form.grant_type.clone());
+  }
+  if form.client_id != CLIENT_ID {
+    return invalid("invalid_client", form.client_id.clone());
+  }
+
+  let mut validation = Validation::new(Algorithm::RS256);
+  validation.set_audience(&[&state.token_url]);
+  validation.set_issuer(&[SERVICE_ACCOUNT_ID]);
+  let claims = match decode::<AssertionClaims>(&form.assertion, &state.public_key, &validation) {
+    Ok(token) => token.claims,
+    Err(err) => return invalid("invalid_grant", err.to_string()),
+  };
+  if claims.sub != SERVICE_ACCOUNT_ID {
+    return invalid("invalid_grant", format!("unknown subject {}", claims.sub));
+  }
+
+  let issued = state.issued.fetch_add(1, Ordering::Relaxed);
+  HttpResponse::Ok().json(json!({
+    "access_token": format!("{ACCESS_TOKEN_PREFIX}{issued}"),
+    "scope": form.scope.clone().unwrap_or_default(),
+    "token_type": "Bearer",
+    "expires_in": ACCESS_TOKEN_LIFETIME_SECS,
+  }))
+}
+
+#[derive(Deserialize)]
+struct RealmPath {
+  realm: String,
+}
+
+#[get("/realm-config/authentication/authenticationtrees/trees")]
+async fn trees(
+  path: web::Path<RealmPath>,
+  req: HttpRequest,
+  state: web::Data<MockState>,
+) -> HttpResponse {
+  if let Err(response) = check_am_request(&req, &state, &path.realm) {
+    return response;
+  }
+  let trees = &state.fixtures.trees[&path.realm];
+
+  HttpResponse::Ok().json(json!({
+    "result": trees,
+    "resultCount": trees.len(),
+    "pagedResultsCookie": null,
+    "totalPagedResultsPolicy": "NONE",
+    "totalPagedResults": -1,
+    "remainingPagedResults": -1,
+  }))
+}
+
+#[derive(Deserialize)]
+struct NodePath {
+  realm: String,
+  node_type: String,
+  node_id: String,
+}
+
+#[get("/realm-config/authentication/authenticationtrees/nodes/{node_type}/{node_id}")]
+async fn node(
+  path: web::Path<NodePath>,
+  req: HttpRequest,
+  state: web::Data<MockState>,
+) -> HttpResponse {
+  if let Err(response) = check_am_request(&req, &state, &path.realm) {
+    return response;
+  }
+
+  // AM only finds a node under the type it was created as.
+  match state.fixtures.nodes.get(&path.node_id) {
+    Some(config) if config.pointer("/_type/_id") == Some(&Value::from(path.node_type.as_str())) => {
+      HttpResponse::Ok().json(config)
+    }
+    _ => am_error(StatusCode::NOT_FOUND, "Not Found"),
+  }
+}
+
+#[derive(Deserialize)]
+struct NodeTypePath {
+  realm: String,
+  node_type: String,
+}
+
+#[derive(Deserialize)]
+struct ActionQuery {
+  #[serde(rename = "_action")]
+  action: String,
+}
+
+#[post("/realm-config/authentication/authenticationtrees/nodes/{node_type}")]
+async fn node_action(
+  path: web::Path<NodeTypePath>,
+  query: web::Query<ActionQuery>,
+  req: HttpRequest,
+  state: web::Data<MockState>,
+) -> HttpResponse {
+  if let Err(response) = check_am_request(&req, &state, &path.realm) {
+    return response;
+  }
+
+  match (path.node_type.as_str(), query.action.as_str()) {
+    ("ScriptedDecisionNode", "schema") => HttpResponse::Ok().json(&state.fixtures.script_schema),
+    _ => am_error(
+      StatusCode::NOT_IMPLEMENTED,
+      "Action not supported by the mock",
+    ),
+  }
+}
+
+#[derive(Deserialize)]
+struct ScriptPath {
+  realm: String,
+  script_id: String,
+}
+
+#[get("/scripts/{script_id}")]
+async fn script(
+  path: web::Path<ScriptPath>,
+  req: HttpRequest,
+  state: web::Data<MockState>,
+) -> HttpResponse {
+  if let Err(response) = check_am_request(&req, &state, &path.realm) {
+    return response;
+  }
+
+  match state.fixtures.scripts.get(&path.script_id) {
+    Some(script) => HttpResponse::Ok().json(script),
+    None => am_error(StatusCode::NOT_FOUND, "Not Found"),
+  }
+}
+
+#[derive(Deserialize)]
+struct LogsQuery {
+  source: String,
+  #[serde(rename = "transactionId", default)]
+  transaction_id: String,
+  #[serde(rename = "_queryFilter", default)]
+  query_filter: String,
+  #[serde(rename = "_fields")]
+  fields: Option<String>,
+  #[serde(rename = "_pagedResultsCookie")]
+  paged_results_cookie: Option<String>,
+  #[serde(rename = "_pageSize")]
+  page_size: Option<usize>,
+  #[serde(rename = "beginTime")]
+  begin_time: Option<DateTime<Utc>>,
+  #[serde(rename = "endTime")]
+  end_time: Option<DateTime<Utc>>,
+}
+
+/// Whether a record from `source` answers a search of `requested`, where `am-everything` covers
+/// every `am-*` source.
+fn source_matches(requested: &str, source: &str) -> bool {
+  match requested.strip_suffix("-everything") {
+    Some(product) => source
+      .strip_prefix(product)
+      .is_some_and(|topic| topic.starts_with('-')),
+    None => requested == source,
+  }
+}
+
+/// A copy of `record` holding only `fields`, each a path such as `payload/principal`.
+fn project(record: &Value, fields: &str) -> Value {
+  let mut projected = json!({});
+  for field in fields
+    .split(',')
+    .map(|field| field.trim().trim_matches('/'))
+  {
+    let pointer = format!("/{field}");
+    let Some(value) = record.pointer(&pointer) else {
+      continue;
+    };
+
+    let mut target = &mut projected;
+    let mut segments = field.split('/').peekable();
+    while let Some(segment) = segments.next() {
+      let Some(object) = target.as_object_mut() else {
+        break;
+      };
+      if segments.peek().is_none() {
+        object.insert(segment.to_string(), value.clone());
+        break;
+      }
+      target = object.entry(segment).or_insert_with(|| json!({}));
+    }
+  }
+  projected
+}
+
+impl MockState {
+  /// The records a search selects, oldest first, or why the logs API would refuse it.
+  fn search(
+    &self,
+    sources: &str,
+    transaction_id: &str,
+    query_filter: &str,
+    window: (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+  ) -> Result<Vec<&Value>, String> {
+    let sources: Vec<&str> = sources.split(',').map(str::trim).collect();
+    if let Some(unknown) = sources
+      .iter()
+      .find(|source| !self.fixtures.sources.iter().any(|known| known == *source))
+    {
+      return Err(format!("Unknown source {unknown}"));
+    }
+
+    let filter = match query_filter.trim() {
+      "" => QueryFilter::True,
+      query_filter => query_filter
+        .parse()
+        .map_err(|err: ShowMeErrors| err.to_string())?,
+    };
+
+    let (begin_time, end_time) = window;
+    if let (Some(begin), Some(end)) = window {
+      if begin > end {
+        return Err("beginTime is after endTime".to_string());
+      }
+      if end - begin > Duration::hours(MAX_WINDOW_HOURS) {
+        return Err(format!("The time range exceeds {MAX_WINDOW_HOURS} hours"));
+      }
+    }
+
+    Ok(
+      self
+        .fixtures
+        .logs
+        .iter()
+        .filter(|record| {
+          let source = record["source"].as_str().unwrap_or_default();
+          sources
+            .iter()
+            .any(|requested| source_matches(requested, source))
+        })
+        .filter(|record| {
+          transaction_id.is_empty()
+            || record
+              .pointer("/payload/transactionId")
+              .and_then(Value::as_str)
+              .is_some_and(|id| id.starts_with(transaction_id))
+        })
+        .filter(|record| {
+          let time = timestamp(record);
+          begin_time.is_none_or(|begin| time.is_some_and(|time| begin <= time))
+            && end_time.is_none_or(|end| time.is_some_and(|time| time <= end))
+        })
+        .filter(|record| filter.matches(record))
+        .collect(),
+    )
+  }
+}
+
+/// A page as the logs API returns it; the cookie is the offset of the next record.
+fn logs_page(result: Vec<Value>, paged_results_cookie: Option<usize>) -> HttpResponse {
+  HttpResponse::Ok().json(json!({
+    "result": result,
+    "resultCount": result.len(),
+    "pagedResultsCookie": paged_results_cookie.map(|offset| offset.to_string()),
+    "totalPagedResultsPolicy": "NONE",
+    "totalPagedResults": -1,
+    "remainingPagedResults": -1,
+  }))
+}
+
+fn cookie_offset(paged_results_cookie: Option<&str>) -> Result<usize, HttpResponse> {
+  paged_results_cookie.map_or(Ok(0), |cookie| {
+    cookie
+      .parse()
+      .map_err(|_| am_error(StatusCode::BAD_REQUEST, "Invalid paged results cookie"))
+  })
+}
+
+#[get("/monitoring/logs")]
+async fn search_logs(
+  query: web::Query<LogsQuery>,
+  req: HttpRequest,
+  state: web::Data<MockState>,
+) -> HttpResponse {
+  if !has_api_key(&req) {
+    return am_error(StatusCode::UNAUTHORIZED, "Invalid API key");
+  }
+  let offset = match cookie_offset(query.paged_results_cookie.as_deref()) {
+    Ok(offset) => offset,
+    Err(response) => return response,
+  };
+  let matching = match state.search(
+    &query.source,
+    &query.transaction_id,
+    &query.query_filter,
+    (query.begin_time, query.end_time),
+  ) {
+    Ok(matching) => matching,
+    Err(message) => return am_error(StatusCode::BAD_REQUEST, &message),
+  };
+
+  let end = (offset + query.page_size.unwrap_or(PAGE_SIZE).max(1)).min(matching.len());
+  let page = matching
+    .get(offset..end)
+    .unwrap_or_default()
+    .iter()
+    .map(|record| match &query.fields {
+      Some(fields) => project(record, fields),
+      None => (*record).clone(),
+    })
+    .collect();
+
+  logs_page(page, (end < matching.len()).then_some(end))
+}
+
+#[derive(Deserialize)]
+struct TailQuery {
+  source: String,
+  #[serde(rename = "_queryFilter", default)]
+  query_filter: String,
+  #[serde(rename = "_pagedResultsCookie")]
+  paged_results_cookie: Option<String>,
+}
+
+/// The first poll returns every matching record, later ones only what came after the cookie,
+/// which for static fixtures is nothing.
+#[get("/monitoring/logs/tail")]
+async fn tail_logs(
+  query: web::Query<TailQuery>,
+  req: HttpRequest,
+  state: web::Data<MockState>,
+) -> HttpResponse {
+  if !has_api_key(&req) {
+    return am_error(StatusCode::UNAUTHORIZED, "Invalid API key");
+  }
+  let offset = match cookie_offset(query.paged_results_cookie.as_deref()) {
+    Ok(offset) => offset,
+    Err(response) => return response,
+  };
+  let matching = match state.search(&query.source, "", &query.query_filter, (None, None)) {
+    Ok(matching) => matching,
+    Err(message) => return am_error(StatusCode::BAD_REQUEST, &message),
+  };
+
+  let page = matching
+    .get(offset..)
+    .unwrap_or_default()
+    .iter()
+    .map(|record| (*record).clone())
+    .collect();
+  logs_page(page, Some(matching.len().max(offset)))
+}
+
+#[get("/monitoring/logs/sources")]
+async fn log_sources(req: HttpRequest, state: web::Data<MockState>) -> HttpResponse {
+  if !has_api_key(&req) {
+    return am_error(StatusCode::UNAUTHORIZED, "Invalid API key");
+  }
+  HttpResponse::Ok().json(json!({ "result": state.fixtures.sources }))
+}
+
+#[derive(Deserialize)]
+struct PrometheusPath {
+  component: String,
+}
+
+#[get("/monitoring/prometheus/{component}")]
+async fn prometheus(
+  path: web::Path<PrometheusPath>,
+  req: HttpRequest,
+  state: web::Data<MockState>,
+) -> HttpResponse {
+  if !has_api_key(&req) {
+    return am_error(StatusCode::UNAUTHORIZED, "Invalid API key");
+  }
+
+  match state.fixtures.prometheus.get(&path.component) {
+    Some(metrics) => HttpResponse::Ok()
+      .content_type("text/plain; version=0.0.4")
+      .body(metrics.clone()),
+    None => am_error(StatusCode::NOT_FOUND, "Not Found"),
+  }
+}
+
+/// The upstream AM and monitoring endpoints the crate calls, minus anything it never uses.
+fn mock_api(cfg: &mut web::ServiceConfig) {
+  cfg
+    .service(access_token)
+    .service(
+      web::scope("/am/json/realms/root/realms/{realm}")
+        .service(trees)
+        .service(node)
+        .service(node_action)
+        .service(script),
+    )
+    .service(tail_logs)
+    .service(log_sources)
+    .service(search_logs)
+    .service(prometheus);
+}
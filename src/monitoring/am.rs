@@ -0,0 +1,130 @@
+use crate::monitoring::metrics::{MetricRate, MetricScrape, Quantile, SampleValue};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Journey outcomes by realm, tree, tree type and outcome.
+pub(crate) const TREE_OUTCOMES: &str = "am_tree_total";
+/// Time spent in each node type of a tree, by outcome, as a summary.
+pub(crate) const NODE_TIMES: &str = "am_tree_node_time_seconds";
+
+/// How often a journey ended each way, e.g. `{"success": 1790, "failure": 92}` for Login.
+#[derive(Serialize, Debug)]
+pub(crate) struct TreeOutcomes {
+  realm: String,
+  tree: String,
+  /// `standard`, or `inner` for journeys only reached from other journeys.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  tree_type: Option<String>,
+  outcomes: BTreeMap<String, f64>,
+  /// Outcomes per second since the previous scrape, empty until there is one.
+  per_second: BTreeMap<String, f64>,
+}
+
+/// How long one node type in a tree takes to reach one outcome.
+#[derive(Serialize, Debug)]
+pub(crate) struct NodeTimer {
+  realm: String,
+  tree: String,
+  node_type: String,
+  outcome: String,
+  count: Option<f64>,
+  mean_seconds: Option<f64>,
+  quantiles: Vec<Quantile>,
+  /// Visits per second since the previous scrape.
+  per_second: Option<f64>,
+}
+
+/// The label, or empty when the sample lacks it.
+fn label(labels: &BTreeMap<String, String>, name: &str) -> String {
+  labels.get(name).cloned().unwrap_or_default()
+}
+
+/// Every tree's outcomes, or just those of `tree`.
+pub(crate) fn tree_outcomes(
+  scrape: &MetricScrape,
+  rates: &[MetricRate],
+  tree: Option<&str>,
+) -> Vec<TreeOutcomes> {
+  let Some(family) = scrape.family(TREE_OUTCOMES) else {
+    return vec![];
+  };
+
+  let mut trees: BTreeMap<(String, String), TreeOutcomes> = BTreeMap::new();
+  for sample in &family.samples {
+    let (realm, name) = (
+      label(&sample.labels, "realm"),
+      label(&sample.labels, "tree"),
+    );
+    if tree.is_some_and(|tree| tree != name) {
+      continue;
+    }
+    let SampleValue::Single { value } = sample.value else {
+      continue;
+    };
+
+    let outcomes = trees
+      .entry((realm.clone(), name.clone()))
+      .or_insert_with(|| TreeOutcomes {
+        realm,
+        tree: name,
+        tree_type: sample.labels.get("type").cloned(),
+        outcomes: BTreeMap::new(),
+        per_second: BTreeMap::new(),
+      });
+    let outcome = label(&sample.labels, "outcome");
+    *outcomes.outcomes.entry(outcome.clone()).or_default() += value;
+    if let Some(rate) = rates
+      .iter()
+      .find(|rate| rate.name == TREE_OUTCOMES && rate.labels == sample.labels)
+    {
+      *outcomes.per_second.entry(outcome).or_default() += rate.per_second;
+    }
+  }
+  trees.into_values().collect()
+}
+
+/// Every node timer, or just those in `tree`.
+pub(crate) fn node_timers(
+  scrape: &MetricScrape,
+  rates: &[MetricRate],
+  tree: Option<&str>,
+) -> Vec<NodeTimer> {
+  let Some(family) = scrape.family(NODE_TIMES) else {
+    return vec![];
+  };
+  let visits = format!("{NODE_TIMES}_count");
+
+  family
+    .samples
+    .iter()
+    .filter(|sample| {
+      tree.is_none_or(|tree| sample.labels.get("tree").is_some_and(|name| name == tree))
+    })
+    .filter_map(|sample| {
+      let SampleValue::Summary {
+        quantiles,
+        sum,
+        count,
+      } = &sample.value
+      else {
+        return None;
+      };
+      Some(NodeTimer {
+        realm: label(&sample.labels, "realm"),
+        tree: label(&sample.labels, "tree"),
+        node_type: label(&sample.labels, "node_type"),
+        outcome: label(&sample.labels, "outcome"),
+        count: *count,
+        mean_seconds: sum
+          .zip(*count)
+          .filter(|(_, count)| *count > 0.0)
+          .map(|(sum, count)| sum / count),
+        quantiles: quantiles.clone(),
+        per_second: rates
+          .iter()
+          .find(|rate| rate.name == visits && rate.labels == sample.labels)
+          .map(|rate| rate.per_second),
+      })
+    })
+    .collect()
+}
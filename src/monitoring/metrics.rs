@@ -0,0 +1,365 @@
+use crate::errors::ShowMeErrors;
+use chrono::{DateTime, Utc};
+use prometheus_parse::{Scrape, Value};
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+
+/// JSON has no infinities or NaN, which exposition text uses for `le="+Inf"` buckets and empty
+/// quantiles, so those are written the way prometheus spells them.
+fn exposition_float<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+  if value.is_finite() {
+    serializer.serialize_f64(*value)
+  } else if value.is_nan() {
+    serializer.serialize_str("NaN")
+  } else if value.is_sign_positive() {
+    serializer.serialize_str("+Inf")
+  } else {
+    serializer.serialize_str("-Inf")
+  }
+}
+
+fn optional_exposition_float<S: Serializer>(
+  value: &Option<f64>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  match value {
+    Some(value) => exposition_float(value, serializer),
+    None => serializer.serialize_none(),
+  }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MetricKind {
+  Counter,
+  Gauge,
+  Histogram,
+  Summary,
+  Untyped,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct Bucket {
+  #[serde(serialize_with = "exposition_float")]
+  pub(crate) le: f64,
+  #[serde(serialize_with = "exposition_float")]
+  pub(crate) count: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct Quantile {
+  #[serde(serialize_with = "exposition_float")]
+  pub(crate) quantile: f64,
+  #[serde(serialize_with = "exposition_float")]
+  pub(crate) value: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub(crate) enum SampleValue {
+  Single {
+    #[serde(serialize_with = "exposition_float")]
+    value: f64,
+  },
+  Histogram {
+    buckets: Vec<Bucket>,
+    #[serde(serialize_with = "optional_exposition_float")]
+    sum: Option<f64>,
+    #[serde(serialize_with = "optional_exposition_float")]
+    count: Option<f64>,
+  },
+  Summary {
+    quantiles: Vec<Quantile>,
+    #[serde(serialize_with = "optional_exposition_float")]
+    sum: Option<f64>,
+    #[serde(serialize_with = "optional_exposition_float")]
+    count: Option<f64>,
+  },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct MetricSample {
+  pub(crate) labels: BTreeMap<String, String>,
+  #[serde(flatten)]
+  pub(crate) value: SampleValue,
+}
+
+impl MetricSample {
+  /// The running totals a rate can be taken of, by name: the value of a counter, or the `_sum`
+  /// and `_count` of a histogram or summary. Gauges go up and down, so they have none.
+  fn totals(&self, family: &MetricFamily) -> Vec<(String, f64)> {
+    match (&self.value, family.kind) {
+      (SampleValue::Single { value }, MetricKind::Counter) => vec![(family.name.clone(), *value)],
+      (SampleValue::Histogram { sum, count, .. }, _)
+      | (SampleValue::Summary { sum, count, .. }, _) => [("_sum", sum), ("_count", count)]
+        .into_iter()
+        .filter_map(|(suffix, total)| Some((format!("{}{suffix}", family.name), (*total)?)))
+        .collect(),
+      _ => vec![],
+    }
+  }
+}
+
+/// Every sample of one metric, e.g. `am_tree_total` across all trees and outcomes.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct MetricFamily {
+  pub(crate) name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) help: Option<String>,
+  pub(crate) kind: MetricKind,
+  pub(crate) samples: Vec<MetricSample>,
+}
+
+/// One product's metrics as of when they were fetched.
+#[derive(Debug, Clone)]
+pub(crate) struct MetricScrape {
+  pub(crate) at: DateTime<Utc>,
+  pub(crate) families: Vec<MetricFamily>,
+}
+
+/// How fast a running total grew between two scrapes.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct MetricRate {
+  /// The counter's name, or a histogram or summary's with `_sum` or `_count` appended.
+  pub(crate) name: String,
+  pub(crate) labels: BTreeMap<String, String>,
+  #[serde(serialize_with = "exposition_float")]
+  pub(crate) per_second: f64,
+}
+
+/// Which families and samples to keep, from `names=am_tree_*,am_session_count` and
+/// `labels=tree=Login,realm=/alpha`.
+#[derive(Debug, Default)]
+pub(crate) struct MetricFilter {
+  /// Exact names, or prefixes when they end in `*`; empty keeps every family.
+  names: Vec<String>,
+  labels: Vec<(String, String)>,
+}
+
+impl MetricFilter {
+  pub(crate) fn new(names: Vec<String>, labels: Vec<String>) -> Result<Self, ShowMeErrors> {
+    let labels = labels
+      .into_iter()
+      .map(|label| {
+        label
+          .split_once('=')
+          .map(|(name, value)| (name.to_string(), value.to_string()))
+          .ok_or_else(|| ShowMeErrors::InvalidMetricFilter(format!("{label} is not name=value")))
+      })
+      .collect::<Result<_, _>>()?;
+    Ok(MetricFilter { names, labels })
+  }
+
+  fn keeps_name(&self, name: &str) -> bool {
+    self.names.is_empty()
+      || self
+        .names
+        .iter()
+        .any(|wanted| match wanted.strip_suffix('*') {
+          Some(prefix) => name.starts_with(prefix),
+          None => name == wanted,
+        })
+  }
+
+  fn keeps_labels(&self, labels: &BTreeMap<String, String>) -> bool {
+    self
+      .labels
+      .iter()
+      .all(|(name, value)| labels.get(name) == Some(value))
+  }
+
+  /// The families and samples this filter keeps, dropping families left with no samples.
+  pub(crate) fn apply(&self, families: &[MetricFamily]) -> Vec<MetricFamily> {
+    families
+      .iter()
+      .filter(|family| self.keeps_name(&family.name))
+      .map(|family| MetricFamily {
+        samples: family
+          .samples
+          .iter()
+          .filter(|sample| self.keeps_labels(&sample.labels))
+          .cloned()
+          .collect(),
+        ..family.clone()
+      })
+      .filter(|family| !family.samples.is_empty())
+      .collect()
+  }
+
+  pub(crate) fn keeps_rate(&self, rate: &MetricRate) -> bool {
+    let family = rate
+      .name
+      .strip_suffix("_sum")
+      .or_else(|| rate.name.strip_suffix("_count"))
+      .filter(|family| self.keeps_name(family));
+    (self.keeps_name(&rate.name) || family.is_some()) && self.keeps_labels(&rate.labels)
+  }
+}
+
+fn labels(labels: &prometheus_parse::Labels) -> BTreeMap<String, String> {
+  labels
+    .iter()
+    .map(|(name, value)| (name.clone(), value.clone()))
+    .collect()
+}
+
+impl MetricScrape {
+  /// Parses exposition text into families, folding each histogram and summary's `_sum` and
+  /// `_count` lines into the sample they belong to.
+  pub(crate) fn parse(text: &str, at: DateTime<Utc>) -> Result<Self, ShowMeErrors> {
+    let scrape = Scrape::parse_at(text.lines().map(|line| Ok(line.to_string())), at)
+      .map_err(|err| ShowMeErrors::InvalidMetrics(err.to_string()))?;
+
+    let mut families: BTreeMap<String, MetricFamily> = BTreeMap::new();
+    // `_sum` and `_count` lines come through untyped; they are matched up once all samples are in.
+    let mut totals: Vec<(String, &'static str, BTreeMap<String, String>, f64)> = vec![];
+
+    for sample in &scrape.samples {
+      let labels = labels(&sample.labels);
+      let (kind, value) = match &sample.value {
+        Value::Counter(value) => (MetricKind::Counter, SampleValue::Single { value: *value }),
+        Value::Gauge(value) => (MetricKind::Gauge, SampleValue::Single { value: *value }),
+        Value::Untyped(value) => {
+          if let Some((family, suffix)) = ["_sum", "_count"]
+            .into_iter()
+            .find_map(|suffix| Some((sample.metric.strip_suffix(suffix)?, suffix)))
+          {
+            totals.push((family.to_string(), suffix, labels.clone(), *value));
+          }
+          (MetricKind::Untyped, SampleValue::Single { value: *value })
+        }
+        Value::Histogram(counts) => {
+          let mut buckets: Vec<Bucket> = counts
+            .iter()
+            .map(|count| Bucket {
+              le: count.less_than,
+              count: count.count,
+            })
+            .collect();
+          buckets.sort_by(|a, b| a.le.total_cmp(&b.le));
+          (
+            MetricKind::Histogram,
+            SampleValue::Histogram {
+              buckets,
+              sum: None,
+              count: None,
+            },
+          )
+        }
+        Value::Summary(counts) => {
+          let mut quantiles: Vec<Quantile> = counts
+            .iter()
+            .map(|count| Quantile {
+              quantile: count.quantile,
+              value: count.count,
+            })
+            .collect();
+          quantiles.sort_by(|a, b| a.quantile.total_cmp(&b.quantile));
+          (
+            MetricKind::Summary,
+            SampleValue::Summary {
+              quantiles,
+              sum: None,
+              count: None,
+            },
+          )
+        }
+      };
+
+      families
+        .entry(sample.metric.clone())
+        .or_insert_with(|| MetricFamily {
+          name: sample.metric.clone(),
+          help: scrape.docs.get(&sample.metric).cloned(),
+          kind,
+          samples: vec![],
+        })
+        .samples
+        .push(MetricSample { labels, value });
+    }
+
+    let mut folded: HashMap<String, usize> = HashMap::new();
+    for (family, suffix, labels, total) in totals {
+      let Some(sample) = families.get_mut(&family).and_then(|family| {
+        family
+          .samples
+          .iter_mut()
+          .find(|sample| sample.labels == labels)
+      }) else {
+        continue;
+      };
+      let (SampleValue::Histogram { sum, count, .. } | SampleValue::Summary { sum, count, .. }) =
+        &mut sample.value
+      else {
+        continue;
+      };
+      *(if suffix == "_sum" { sum } else { count }) = Some(total);
+      *folded.entry(format!("{family}{suffix}")).or_default() += 1;
+    }
+
+    // A `_sum` or `_count` family whose every sample was folded has nothing left to say.
+    for (name, count) in folded {
+      if families
+        .get(&name)
+        .is_some_and(|family| family.samples.len() == count)
+      {
+        families.remove(&name);
+      }
+    }
+
+    let mut families: Vec<MetricFamily> = families.into_values().collect();
+    for family in &mut families {
+      family.samples.sort_by(|a, b| a.labels.cmp(&b.labels));
+    }
+    Ok(MetricScrape { at, families })
+  }
+
+  pub(crate) fn family(&self, name: &str) -> Option<&MetricFamily> {
+    self.families.iter().find(|family| family.name == name)
+  }
+
+  fn totals(&self) -> HashMap<(String, BTreeMap<String, String>), f64> {
+    self
+      .families
+      .iter()
+      .flat_map(|family| {
+        family.samples.iter().flat_map(move |sample| {
+          sample
+            .totals(family)
+            .into_iter()
+            .map(|(name, total)| ((name, sample.labels.clone()), total))
+        })
+      })
+      .collect()
+  }
+
+  /// Per second growth of every running total since `previous`. A total that went down was reset,
+  /// e.g. by a restart, so all of its current value counts as growth.
+  pub(crate) fn rates_since(&self, previous: &MetricScrape) -> Vec<MetricRate> {
+    let seconds = (self.at - previous.at).num_milliseconds() as f64 / 1000.0;
+    if seconds <= 0.0 {
+      return vec![];
+    }
+
+    let before = previous.totals();
+    let mut rates: Vec<MetricRate> = self
+      .totals()
+      .into_iter()
+      .filter_map(|((name, labels), total)| {
+        let earlier = *before.get(&(name.clone(), labels.clone()))?;
+        let growth = if total < earlier {
+          total
+        } else {
+          total - earlier
+        };
+        Some(MetricRate {
+          name,
+          labels,
+          per_second: growth / seconds,
+        })
+      })
+      .collect();
+    rates.sort_by(|a, b| (&a.name, &a.labels).cmp(&(&b.name, &b.labels)));
+    rates
+  }
+}
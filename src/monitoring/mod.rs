@@ -0,0 +1,4 @@
+pub(crate) mod am;
+pub(crate) mod metrics;
+pub(crate) mod scrapes;
+pub(crate) mod service;
@@ -0,0 +1,90 @@
+use crate::errors::ShowMeErrors;
+use crate::monitoring::metrics::{MetricRate, MetricScrape};
+use crate::ping_logs::client::LogClient;
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Requests closer together than this share a scrape, so rates never span a sliver of time.
+const MIN_SCRAPE_INTERVAL_SECS: i64 = 15;
+
+/// The products a tenant exposes prometheus metrics for, as named in the path.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Product {
+  Am,
+  Idm,
+}
+
+impl Product {
+  fn path(&self) -> &'static str {
+    match self {
+      Product::Am => "am",
+      Product::Idm => "idm",
+    }
+  }
+}
+
+/// A product's latest scrape and the one before it, which rates are taken between.
+#[derive(Clone)]
+pub(crate) struct ScrapePair {
+  pub(crate) latest: Arc<MetricScrape>,
+  pub(crate) previous: Option<Arc<MetricScrape>>,
+}
+
+/// The scrapes of each product in one environment.
+#[derive(Default)]
+pub(crate) struct Scrapes {
+  /// Held across a fetch, so concurrent requests wait for one scrape instead of each making one.
+  products: tokio::sync::Mutex<HashMap<Product, ScrapePair>>,
+}
+
+async fn scrape(
+  log_client: &LogClient,
+  dom: &str,
+  product: Product,
+) -> Result<MetricScrape, ShowMeErrors> {
+  let text = log_client
+    .send(log_client.authorized_get(&format!("{dom}/monitoring/prometheus/{}", product.path())))
+    .await?
+    .text()
+    .await?;
+  MetricScrape::parse(&text, Utc::now())
+}
+
+impl Scrapes {
+  /// The product's scrapes, scraping it again first unless the latest is recent.
+  pub(crate) async fn current(
+    &self,
+    log_client: &LogClient,
+    dom: &str,
+    product: Product,
+  ) -> Result<ScrapePair, ShowMeErrors> {
+    let mut products = self.products.lock().await;
+    if let Some(pair) = products.get(&product)
+      && Utc::now() - pair.latest.at < Duration::seconds(MIN_SCRAPE_INTERVAL_SECS)
+    {
+      return Ok(pair.clone());
+    }
+
+    let latest = Arc::new(scrape(log_client, dom, product).await?);
+    let pair = ScrapePair {
+      previous: products.remove(&product).map(|pair| pair.latest),
+      latest,
+    };
+    products.insert(product, pair.clone());
+    Ok(pair)
+  }
+}
+
+impl ScrapePair {
+  /// Growth of every running total between the two scrapes; none until there are two.
+  pub(crate) fn rates(&self) -> Vec<MetricRate> {
+    self
+      .previous
+      .as_ref()
+      .map(|previous| self.latest.rates_since(previous))
+      .unwrap_or_default()
+  }
+}
@@ -0,0 +1,149 @@
+use crate::AppMutState;
+use crate::errors::ShowMeErrors;
+use crate::monitoring::am::{NodeTimer, TreeOutcomes, node_timers, tree_outcomes};
+use crate::monitoring::metrics::{MetricFamily, MetricFilter, MetricRate};
+use crate::monitoring::scrapes::{Product, ScrapePair};
+use crate::ping_logs::service::comma_separated;
+use actix_web::{get, web};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+struct MetricQuery {
+  /// Comma separated metric names, each exact or a prefix ending in `*`, e.g. `am_tree_*`.
+  names: Option<String>,
+  /// Comma separated `name=value` pairs every kept sample must have, e.g. `tree=Login`.
+  labels: Option<String>,
+}
+
+impl MetricQuery {
+  fn filter(&self) -> Result<MetricFilter, ShowMeErrors> {
+    MetricFilter::new(
+      comma_separated(self.names.as_deref()),
+      comma_separated(self.labels.as_deref()),
+    )
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct TreeQuery {
+  tree: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Metrics {
+  scraped_at: DateTime<Utc>,
+  families: Vec<MetricFamily>,
+}
+
+#[derive(Serialize)]
+struct Rates {
+  /// The earlier scrape; absent on the first request, when there is nothing to compare with yet.
+  since: Option<DateTime<Utc>>,
+  scraped_at: DateTime<Utc>,
+  rates: Vec<MetricRate>,
+}
+
+#[derive(Serialize)]
+struct AmView<T> {
+  scraped_at: DateTime<Utc>,
+  since: Option<DateTime<Utc>>,
+  #[serde(flatten)]
+  view: T,
+}
+
+#[derive(Serialize)]
+struct Trees {
+  trees: Vec<TreeOutcomes>,
+}
+
+#[derive(Serialize)]
+struct Nodes {
+  nodes: Vec<NodeTimer>,
+}
+
+async fn current(data: &AppMutState, product: Product) -> Result<ScrapePair, ShowMeErrors> {
+  data
+    .scrapes
+    .current(&data.log_client, &data.token.dom, product)
+    .await
+}
+
+/// Authentication tree outcomes per tree, e.g. for the success rate of the journey on screen.
+#[get("/am/trees")]
+async fn am_trees(
+  query: web::Query<TreeQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<AmView<Trees>>, ShowMeErrors> {
+  let scrapes = current(&data, Product::Am).await?;
+  Ok(web::Json(AmView {
+    scraped_at: scrapes.latest.at,
+    since: scrapes.previous.as_ref().map(|previous| previous.at),
+    view: Trees {
+      trees: tree_outcomes(&scrapes.latest, &scrapes.rates(), query.tree.as_deref()),
+    },
+  }))
+}
+
+/// Time spent per node type and outcome, to set beside the nodes of a journey's flow.
+#[get("/am/nodes")]
+async fn am_nodes(
+  query: web::Query<TreeQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<AmView<Nodes>>, ShowMeErrors> {
+  let scrapes = current(&data, Product::Am).await?;
+  Ok(web::Json(AmView {
+    scraped_at: scrapes.latest.at,
+    since: scrapes.previous.as_ref().map(|previous| previous.at),
+    view: Nodes {
+      nodes: node_timers(&scrapes.latest, &scrapes.rates(), query.tree.as_deref()),
+    },
+  }))
+}
+
+/// The product's metric families, e.g. `/monitoring/am?names=am_session_count`.
+#[get("/{product}")]
+async fn metrics(
+  product: web::Path<Product>,
+  query: web::Query<MetricQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Metrics>, ShowMeErrors> {
+  let filter = query.filter()?;
+  let scrapes = current(&data, *product).await?;
+  Ok(web::Json(Metrics {
+    scraped_at: scrapes.latest.at,
+    families: filter.apply(&scrapes.latest.families),
+  }))
+}
+
+/// Per second growth of the product's counters, histograms and summaries between its last two
+/// scrapes.
+#[get("/{product}/rates")]
+async fn rates(
+  product: web::Path<Product>,
+  query: web::Query<MetricQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Rates>, ShowMeErrors> {
+  let filter = query.filter()?;
+  let scrapes = current(&data, *product).await?;
+  Ok(web::Json(Rates {
+    since: scrapes.previous.as_ref().map(|previous| previous.at),
+    scraped_at: scrapes.latest.at,
+    rates: scrapes
+      .rates()
+      .into_iter()
+      .filter(|rate| filter.keeps_rate(rate))
+      .collect(),
+  }))
+}
+
+pub(crate) fn monitoring_api(cfg: &mut web::ServiceConfig) {
+  cfg.service(
+    web::scope("/monitoring")
+      // Registered ahead of `/{product}/...` so "am/trees" isn't taken for a product's route.
+      .service(am_trees)
+      .service(am_nodes)
+      .service(metrics)
+      .service(rates),
+  );
+}
@@ -0,0 +1,231 @@
+use crate::config::EnvironmentConfig;
+use crate::errors::ShowMeErrors;
+use crate::ping_logs::logs::Logs;
+use crate::ping_logs::query_filter::QueryFilter;
+use crate::ping_logs::rate_limit::RateLimiter;
+use crate::ping_logs::window::TimeWindow;
+use reqwest::{Client, RequestBuilder, Response};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const LOG_SOURCES: &str = "am-everything,idm-everything";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One search against the logs API: which sources and transaction, which records, which fields
+/// of them and over what time span.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LogQuery {
+  sources: Vec<String>,
+  transaction_id: String,
+  query_filter: Option<String>,
+  fields: Vec<String>,
+  window: TimeWindow,
+}
+
+impl LogQuery {
+  /// Records from any transaction; the API treats a blank transaction id as a `*` search.
+  pub(crate) fn all() -> Self {
+    LogQuery::default()
+  }
+
+  pub(crate) fn transaction(transaction_id: impl Into<String>) -> Self {
+    LogQuery {
+      transaction_id: transaction_id.into(),
+      ..LogQuery::default()
+    }
+  }
+
+  pub(crate) fn filter(self, query_filter: QueryFilter) -> Self {
+    LogQuery {
+      query_filter: Some(query_filter.to_string()),
+      ..self
+    }
+  }
+
+  pub(crate) fn window(self, window: TimeWindow) -> Self {
+    LogQuery { window, ..self }
+  }
+
+  /// Searches these sources instead of `am-everything` and `idm-everything`.
+  pub(crate) fn sources(self, sources: Vec<String>) -> Self {
+    LogQuery { sources, ..self }
+  }
+
+  /// Only returns these fields of each record, e.g. `timestamp` or `payload/principal`.
+  pub(crate) fn fields(self, fields: Vec<String>) -> Self {
+    LogQuery { fields, ..self }
+  }
+
+  /// The same search once per sub-window the API will accept, oldest first.
+  pub(crate) fn split(&self) -> Vec<LogQuery> {
+    self
+      .window
+      .split()
+      .into_iter()
+      .map(|window| self.clone().window(window))
+      .collect()
+  }
+
+  fn params(&self) -> Vec<(&str, String)> {
+    let source = if self.sources.is_empty() {
+      LOG_SOURCES.to_string()
+    } else {
+      self.sources.join(",")
+    };
+
+    let mut params = vec![
+      ("source", source),
+      ("transactionId", self.transaction_id.clone()),
+      (
+        "_queryFilter",
+        self.query_filter.clone().unwrap_or_default(),
+      ),
+    ];
+    if !self.fields.is_empty() {
+      params.push(("_fields", self.fields.join(",")));
+    }
+    params
+  }
+}
+
+/// An environment's monitoring logs API: a pooled client, the base URL and the key pair it expects.
+/// AM calls go through the same client so they share its timeouts and rate limiting.
+#[derive(Clone)]
+pub(crate) struct LogClient {
+  client: Client,
+  /// One per `host:port`, since each host keeps its own rate-limit budget.
+  limiters: Arc<Mutex<HashMap<String, RateLimiter>>>,
+  url: String,
+  key: String,
+  secret: String,
+}
+
+impl LogClient {
+  pub(crate) fn new(config: &EnvironmentConfig) -> Result<Self, ShowMeErrors> {
+    Ok(LogClient {
+      client: Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()?,
+      limiters: Arc::new(Mutex::new(HashMap::new())),
+      url: config.log_url.clone(),
+      key: config.log_key.clone(),
+      secret: config.log_secret.clone(),
+    })
+  }
+
+  /// The pooled client, for calls that don't need the logs API key pair.
+  pub(crate) fn http(&self) -> &Client {
+    &self.client
+  }
+
+  /// A GET carrying the logs API key pair, which the prometheus endpoints accept too.
+  pub(crate) fn authorized_get(&self, url: &str) -> RequestBuilder {
+    self
+      .client
+      .get(url)
+      .header("x-api-key", &self.key)
+      .header("x-api-secret", &self.secret)
+  }
+
+  /// Lists the log sources, which is enough to prove the URL and key pair work.
+  pub(crate) async fn check(&self) -> Result<(), ShowMeErrors> {
+    self.sources().await.map(|_| ())
+  }
+
+  /// The sources the tenant can be searched by, e.g. `am-access` or `idm-sync`.
+  pub(crate) async fn sources(&self) -> Result<Vec<String>, ShowMeErrors> {
+    #[derive(Deserialize)]
+    struct Sources {
+      result: Vec<String>,
+    }
+
+    let response = self
+      .send(self.authorized_get(&format!("{}/sources", self.url)))
+      .await?;
+    let sources: Sources = serde_json::from_slice(&response.bytes().await?)?;
+    Ok(sources.result)
+  }
+
+  /// One page of `query`, parsed as `Logs` or, for projected and non-AM sources, raw JSON.
+  pub(crate) async fn logs_page<P: DeserializeOwned>(
+    &self,
+    query: &LogQuery,
+    paged_results_cookie: Option<&str>,
+  ) -> Result<P, ShowMeErrors> {
+    let mut params = query.params();
+    if let Some(cookie) = paged_results_cookie {
+      params.push(("_pagedResultsCookie", cookie.to_string()));
+    }
+
+    self
+      .fetch_page(
+        self
+          .authorized_get(&self.url)
+          .query(&params)
+          .query(&query.window.query_params()),
+      )
+      .await
+  }
+
+  /// Polls `/monitoring/logs/tail`, which only returns records newer than the previous cookie.
+  pub(crate) async fn tail_page(
+    &self,
+    query_filter: &QueryFilter,
+    paged_results_cookie: Option<&str>,
+  ) -> Result<Logs, ShowMeErrors> {
+    let query_filter = query_filter.to_string();
+    let mut params = vec![("source", LOG_SOURCES), ("_queryFilter", query_filter.as_str())];
+    if let Some(cookie) = paged_results_cookie {
+      params.push(("_pagedResultsCookie", cookie));
+    }
+
+    self
+      .fetch_page(
+        self
+          .authorized_get(&format!("{}/tail", self.url))
+          .query(&params),
+      )
+      .await
+  }
+
+  /// Sends any Ping request, logs or AM, through the rate limiter of the host it is addressed to.
+  pub(crate) async fn send(&self, request: RequestBuilder) -> Result<Response, ShowMeErrors> {
+    Ok(self.send_unchecked(request).await?.error_for_status()?)
+  }
+
+  /// Like `send`, but hands back error responses too, for callers that report their body.
+  pub(crate) async fn send_unchecked(
+    &self,
+    request: RequestBuilder,
+  ) -> Result<Response, ShowMeErrors> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let url = request.url();
+    let host = format!(
+      "{}:{}",
+      url.host_str().unwrap_or_default(),
+      url.port_or_known_default().unwrap_or_default()
+    );
+
+    let limiter = self
+      .limiters
+      .lock()
+      .map_err(|_| ShowMeErrors::SharedLocking("rate limiters".into()))?
+      .entry(host)
+      .or_default()
+      .clone();
+    limiter
+      .send(RequestBuilder::from_parts(client, request))
+      .await
+  }
+
+  async fn fetch_page<P: DeserializeOwned>(&self, request: RequestBuilder) -> Result<P, ShowMeErrors> {
+    let response = self.send(request).await?;
+    Ok(serde_json::from_slice(&response.bytes().await?)?)
+  }
+}
@@ -1,17 +1,26 @@
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 use crate::errors::ShowMeErrors;
+use crate::ping_logs::client::{LogClient, LogQuery};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum Level {
+  Trace,
   Debug,
+  /// AM writes `WARN`, IDM `WARNING`; both read as this one level so filters catch either.
+  #[serde(alias = "WARN")]
   Warning,
-  Warn,
   Info,
   Error,
+  Fatal,
+  /// Anything else a logger chose to print, kept verbatim.
+  #[serde(untagged)]
+  Other(String),
 }
 
 
@@ -19,47 +28,272 @@ pub enum Level {
 #[serde(rename_all = "camelCase")]
 pub struct NodeOutcomeInfo {
   node_extra_logging: Option<serde_json::Map<String, serde_json::Value>>,
-  node_id: String,
+  /// Missing from logs written before AM started recording node ids.
+  pub(crate) node_id: Option<String>,
+  pub(crate) tree_name: Option<String>,
   pub(crate) node_outcome: String,
   pub(crate) display_name: String,
 }
 
+/// The fields AM and IDM audit events share, whatever their topic.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+  #[serde(rename = "_id")]
+  id: Option<String>,
+  event_name: Option<String>,
+  transaction_id: Option<String>,
+  #[serde(default)]
+  tracking_ids: Vec<String>,
+  user_id: Option<String>,
+  realm: Option<String>,
+  component: Option<String>,
+  level: Option<Level>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationEvent {
+  #[serde(flatten)]
+  event: AuditEvent,
+  principal: Option<Vec<String>>,
+  result: Option<String>,
+  /// Left as JSON: node outcomes and module logins put different things in `info`.
+  #[serde(default)]
+  entries: Vec<serde_json::Value>,
+  #[serde(flatten)]
+  rest: serde_json::Map<String, serde_json::Value>,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct NodeOutcome {
-  pub(crate) info: NodeOutcomeInfo,
+pub struct AccessEvent {
+  #[serde(flatten)]
+  event: AuditEvent,
+  client: Option<serde_json::Value>,
+  http: Option<serde_json::Value>,
+  request: Option<serde_json::Value>,
+  response: Option<serde_json::Value>,
+  #[serde(flatten)]
+  rest: serde_json::Map<String, serde_json::Value>,
 }
 
+/// Activity and config events: an operation on an object, with its state before and after.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct PingPayload {
+pub struct ChangeEvent {
+  #[serde(flatten)]
+  event: AuditEvent,
+  object_id: Option<String>,
+  operation: Option<String>,
+  changed_fields: Option<Vec<String>>,
+  #[serde(flatten)]
+  rest: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A structured line from a logger, which is where scripts' `logger.error(...)` output lands.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugLog {
+  #[serde(rename = "_id")]
+  id: Option<String>,
   context: Option<String>,
-  level: Level,
-  pub(crate) entries: Option<Vec<NodeOutcome>>,
+  level: Option<Level>,
   logger: Option<String>,
   message: Option<String>,
-  pub(crate) transaction_id: String,
-  pub(crate) tracking_ids: Vec<String>,
+  transaction_id: Option<String>,
+  #[serde(default)]
+  tracking_ids: Vec<String>,
+  #[serde(flatten)]
+  rest: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A log record's payload. Serializes untagged, so the UI sees the payload's own fields whichever
+/// variant a record landed in.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PingPayload {
+  Authentication(AuthenticationEvent),
+  Access(AccessEvent),
+  Activity(ChangeEvent),
+  Config(ChangeEvent),
+  Debug(DebugLog),
+  /// `text/plain` records, e.g. container stdout.
+  Text(String),
+  /// Anything that didn't fit the variant its source suggested.
+  Raw(serde_json::Value),
+}
+
+impl PingPayload {
+  /// Picks a variant from the record's source (`am-access`, `idm-activity`, ...), falling back to
+  /// the payload's shape for sources like `am-everything`, and to `Raw` when parsing fails.
+  fn classify(source: &str, payload: serde_json::Value) -> Self {
+    let payload = match payload {
+      serde_json::Value::String(text) => return PingPayload::Text(text),
+      payload => payload,
+    };
+
+    let topic = match source.split_once('-') {
+      Some((_, topic)) => topic,
+      None => source,
+    };
+    let has = |field: &str| payload.get(field).is_some();
+
+    let typed = match topic {
+      "authentication" => serde_json::from_value(payload.clone()).map(PingPayload::Authentication),
+      "access" => serde_json::from_value(payload.clone()).map(PingPayload::Access),
+      "activity" => serde_json::from_value(payload.clone()).map(PingPayload::Activity),
+      "config" => serde_json::from_value(payload.clone()).map(PingPayload::Config),
+      _ if has("entries") => serde_json::from_value(payload.clone()).map(PingPayload::Authentication),
+      _ if has("http") || has("response") => serde_json::from_value(payload.clone()).map(PingPayload::Access),
+      _ if has("logger") || has("message") => serde_json::from_value(payload.clone()).map(PingPayload::Debug),
+      _ => return PingPayload::Raw(payload),
+    };
+
+    typed.unwrap_or(PingPayload::Raw(payload))
+  }
+
+  fn event(&self) -> Option<&AuditEvent> {
+    match self {
+      PingPayload::Authentication(event) => Some(&event.event),
+      PingPayload::Access(event) => Some(&event.event),
+      PingPayload::Activity(event) | PingPayload::Config(event) => Some(&event.event),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn id(&self) -> Option<&str> {
+    match self {
+      PingPayload::Debug(log) => log.id.as_deref(),
+      _ => self.event()?.id.as_deref(),
+    }
+  }
+
+  pub(crate) fn level(&self) -> Option<&Level> {
+    match self {
+      PingPayload::Debug(log) => log.level.as_ref(),
+      _ => self.event()?.level.as_ref(),
+    }
+  }
+
+  pub(crate) fn transaction_id(&self) -> Option<&str> {
+    match self {
+      PingPayload::Debug(log) => log.transaction_id.as_deref(),
+      _ => self.event()?.transaction_id.as_deref(),
+    }
+  }
+
+  pub(crate) fn logger(&self) -> Option<&str> {
+    match self {
+      PingPayload::Debug(log) => log.logger.as_deref(),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn tracking_ids(&self) -> &[String] {
+    match self {
+      PingPayload::Debug(log) => &log.tracking_ids,
+      _ => self.event().map(|event| event.tracking_ids.as_slice()).unwrap_or_default(),
+    }
+  }
+
+  /// The node outcome a tree node logged, if this is one.
+  pub(crate) fn node_outcome(&self) -> Option<NodeOutcomeInfo> {
+    let PingPayload::Authentication(event) = self else {
+      return None;
+    };
+    serde_json::from_value(event.entries.first()?.get("info")?.clone()).ok()
+  }
+}
+
+/// The record as it comes off the wire, before its payload is classified. Every field is taken as
+/// whatever JSON arrived, so one malformed record can't fail the page it arrived in.
+#[derive(Deserialize)]
+struct RawLog {
+  #[serde(default)]
+  payload: serde_json::Value,
+  #[serde(default)]
+  timestamp: serde_json::Value,
+  #[serde(rename = "type", default)]
+  data_type: serde_json::Value,
+  #[serde(default)]
+  source: serde_json::Value,
+}
+
+/// Reads RFC 3339, the same without an offset (taken as UTC), or epoch milliseconds.
+fn parse_timestamp(timestamp: &serde_json::Value) -> Option<DateTime<Utc>> {
+  match timestamp {
+    serde_json::Value::String(text) => DateTime::parse_from_rfc3339(text)
+      .map(|time| time.with_timezone(&Utc))
+      .ok()
+      .or_else(|| {
+        ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+          .iter()
+          .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+          .map(|time| time.and_utc())
+      }),
+    serde_json::Value::Number(millis) => DateTime::from_timestamp_millis(millis.as_i64()?),
+    _ => None,
+  }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "RawLog")]
 pub struct ResultingLog {
   pub(crate) payload: PingPayload,
-  pub(crate) timestamp: DateTime<Utc>,
+  /// `None` when the record had no timestamp or one that couldn't be read.
+  pub(crate) timestamp: Option<DateTime<Utc>>,
   #[serde(rename = "type")]
   data_type: String,
   source: String,
 }
 
+impl From<RawLog> for ResultingLog {
+  /// A record whose envelope can't be read keeps its payload as `Raw` rather than being
+  /// classified by a source or timestamp that may be wrong.
+  fn from(raw: RawLog) -> Self {
+    let timestamp = parse_timestamp(&raw.timestamp);
+    let (data_type, source) = (raw.data_type.as_str(), raw.source.as_str());
+    let readable = (timestamp.is_some() || raw.timestamp.is_null())
+      && (data_type.is_some() || raw.data_type.is_null())
+      && (source.is_some() || raw.source.is_null());
+
+    let source = source.unwrap_or_default().to_string();
+    ResultingLog {
+      payload: if readable {
+        PingPayload::classify(&source, raw.payload)
+      } else {
+        PingPayload::Raw(raw.payload)
+      },
+      timestamp,
+      data_type: data_type.unwrap_or_default().to_string(),
+      source,
+    }
+  }
+}
+
+impl ResultingLog {
+  /// Identifies a record across overlapping tail polls, falling back to its content when AIC gives no `_id`.
+  pub(crate) fn dedupe_key(&self) -> String {
+    match self.payload.id() {
+      Some(id) => id.to_string(),
+      None => format!(
+        "{}|{}",
+        self.timestamp.map(|timestamp| timestamp.to_rfc3339()).unwrap_or_default(),
+        serde_json::to_string(&self.payload).unwrap_or_default()
+      ),
+    }
+  }
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Logs {
   pub(crate) result: Vec<ResultingLog>,
-  paged_result_cooke: Option<String>,
+  pub(crate) paged_results_cookie: Option<String>,
   total_paged_results_policy: String,
-  total_paged_results: i16,
-  remaining_paged_results: i16,
+  total_paged_results: i64,
+  remaining_paged_results: i64,
 }
 
 impl Logs {
@@ -67,7 +301,7 @@ impl Logs {
     let result = self
       .result
       .iter()
-      .filter(|t| t.payload.level == level)
+      .filter(|t| t.payload.level() == Some(&level))
       .cloned()
       .collect::<Vec<_>>()
       .clone();
@@ -75,39 +309,203 @@ impl Logs {
   }
 }
 
+/// A page of free-form search results, kept as raw JSON because other sources and `_fields`
+/// projections don't have the shape of an AM node outcome log.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchPage {
+  result: Vec<serde_json::Value>,
+  paged_results_cookie: Option<String>,
+}
+
+/// A page from the logs API: its records and the cookie for the next page, if any.
+pub(crate) trait LogPage: DeserializeOwned + 'static {
+  type Record: 'static;
+
+  fn into_parts(self) -> (Vec<Self::Record>, Option<String>);
+}
+
+impl LogPage for Logs {
+  type Record = ResultingLog;
+
+  fn into_parts(self) -> (Vec<ResultingLog>, Option<String>) {
+    (self.result, self.paged_results_cookie)
+  }
+}
+
+impl LogPage for SearchPage {
+  type Record = serde_json::Value;
+
+  fn into_parts(self) -> (Vec<serde_json::Value>, Option<String>) {
+    (self.result, self.paged_results_cookie)
+  }
+}
+
+impl From<Vec<ResultingLog>> for Logs {
+  fn from(result: Vec<ResultingLog>) -> Self {
+    Logs {
+      total_paged_results: result.len() as i64,
+      result,
+      ..Logs::default()
+    }
+  }
+}
+
+/// Caps how far `stream_logs` follows `_pagedResultsCookie`, so a broad query can't page forever.
+///
+/// `max_pages` applies to each 24h sub-window, `max_records` to the whole stream.
+#[derive(Debug, Clone, Copy)]
+pub struct LogPaging {
+  pub max_pages: Option<usize>,
+  pub max_records: Option<usize>,
+}
+
+const DEFAULT_MAX_PAGES: usize = 50;
+
+impl Default for LogPaging {
+  fn default() -> Self {
+    Self {
+      max_pages: Some(DEFAULT_MAX_PAGES),
+      max_records: None,
+    }
+  }
+}
+
+impl LogPaging {
+  pub fn new(max_pages: Option<usize>, max_records: Option<usize>) -> Self {
+    Self {
+      max_pages: max_pages.or(Self::default().max_pages),
+      max_records,
+    }
+  }
+}
+
+enum PageCursor {
+  First,
+  Next(String),
+  Done,
+}
+
+fn stream_window<'a, P: LogPage>(
+  client: &'a LogClient,
+  query: LogQuery,
+  paging: LogPaging,
+  truncated: Option<&'a AtomicBool>,
+) -> impl Stream<Item = Result<P::Record, ShowMeErrors>> + 'a {
+  stream::unfold((query, PageCursor::First, 0), move |(query, cursor, pages)| async move {
+    let cookie = match cursor {
+      PageCursor::Done => return None,
+      PageCursor::First => None,
+      PageCursor::Next(cookie) => Some(cookie),
+    };
+
+    if paging.max_pages.is_some_and(|max| pages >= max) {
+      truncated.inspect(|truncated| truncated.store(true, Ordering::Relaxed));
+      return None;
+    }
+
+    match client.logs_page::<P>(&query, cookie.as_deref()).await {
+      Ok(page) => {
+        let (result, paged_results_cookie) = page.into_parts();
+        let next = match paged_results_cookie {
+          Some(cookie) if !cookie.is_empty() => PageCursor::Next(cookie),
+          _ => PageCursor::Done,
+        };
+        Some((Ok(result), (query, next, pages + 1)))
+      }
+      Err(e) => Some((Err(e), (query, PageCursor::Done, pages + 1))),
+    }
+  })
+  .flat_map(|page| {
+    stream::iter(match page {
+      Ok(result) => result.into_iter().map(Ok).collect(),
+      Err(e) => vec![Err(e)],
+    })
+  })
+}
+
+/// Walks every page of a log query, yielding records as each page arrives.
+///
+/// Windows wider than the API allows are fetched as consecutive sub-windows, oldest first.
+/// A failed page is yielded as an error and ends that sub-window.
+fn stream_pages<'a, P: LogPage>(
+  client: &'a LogClient,
+  query: LogQuery,
+  paging: LogPaging,
+  truncated: Option<&'a AtomicBool>,
+) -> impl Stream<Item = Result<P::Record, ShowMeErrors>> + 'a {
+  stream::iter(query.split())
+    .flat_map(move |sub_query| stream_window::<P>(client, sub_query, paging, truncated))
+    .take(paging.max_records.unwrap_or(usize::MAX))
+}
+
+pub(crate) fn stream_logs(
+  client: &LogClient,
+  query: LogQuery,
+  paging: LogPaging,
+) -> impl Stream<Item = Result<ResultingLog, ShowMeErrors>> + '_ {
+  stream_pages::<Logs>(client, query, paging, None)
+}
+
+/// Every record of a query, and whether `LogPaging` stopped before the last of them.
+#[derive(Default)]
+pub(crate) struct CollectedLogs {
+  pub(crate) logs: Vec<ResultingLog>,
+  pub(crate) truncated: bool,
+}
+
+/// Like `stream_logs` collected, but for callers that keep the result and so need to know
+/// whether `max_pages` or `max_records` cut it short.
+pub(crate) async fn collect_logs(
+  client: &LogClient,
+  query: LogQuery,
+  paging: LogPaging,
+) -> Result<CollectedLogs, ShowMeErrors> {
+  let truncated = AtomicBool::new(false);
+  let logs: Vec<ResultingLog> = stream_pages::<Logs>(client, query, paging, Some(&truncated))
+    .try_collect()
+    .await?;
+
+  Ok(CollectedLogs {
+    // Hitting the record cap exactly may have left more behind it.
+    truncated: truncated.into_inner() || paging.max_records.is_some_and(|max| logs.len() >= max),
+    logs,
+  })
+}
+
+/// Like `stream_logs`, but for any source or projection, so records come back as raw JSON.
+pub(crate) fn search_logs(
+  client: &LogClient,
+  query: LogQuery,
+  paging: LogPaging,
+) -> impl Stream<Item = Result<serde_json::Value, ShowMeErrors>> + '_ {
+  stream_pages::<SearchPage>(client, query, paging, None)
+}
+
 pub(crate) async fn get_logs(
-  client: &Client,
-  transaction_id: &str,
-  query_filter: Option<&str>,
+  client: &LogClient,
+  query: LogQuery,
+  paging: LogPaging,
 ) -> Result<Logs, ShowMeErrors> {
-  let params = [
-    ("source", "am-everything,idm-everything"),
-    ("transactionId", transaction_id),
-    (
-      "_queryFilter",
-      query_filter.unwrap_or_else(|| ""),
-    ),
-  ];
-
-  let url = std::env::var("SANDBOX")?;
-  let key = std::env::var("PING_KEY")?;
-  let sec = std::env::var("PING_SEC")?;
-  match client
-    .get(url)
-    .query(&params)
-    .header("x-api-key", key)
-    .header("x-api-secret", sec)
-    .send()
-    .await
-  {
-    Ok(res) => match res.bytes().await {
-      Ok(bty) => Ok(serde_json::from_slice(&bty)?),
-      Err(e) => {
-        Err(ShowMeErrors::PingApiError(/* reqwest::Error */ e))
-      }
-    },
-    Err(e) => {
-      Err(ShowMeErrors::PingApiError(/* reqwest::Error */ e))
+  let mut result: Vec<ResultingLog> = stream_logs(client, query, paging).try_collect().await?;
+
+  result.sort_by_key(|log| log.timestamp);
+
+  Ok(Logs::from(result))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn warn_and_warning_read_as_one_level() {
+    for raw in [r#""WARN""#, r#""WARNING""#] {
+      assert_eq!(serde_json::from_str::<Level>(raw).unwrap(), Level::Warning);
     }
+    assert_eq!(
+      serde_json::from_str::<Level>(r#""NOTICE""#).unwrap(),
+      Level::Other("NOTICE".into())
+    );
   }
 }
@@ -1,3 +1,8 @@
+pub(crate) mod client;
 pub(crate) mod logs;
+pub(crate) mod query_filter;
+pub(crate) mod rate_limit;
 pub mod service;
-// mod watchers;
+pub(crate) mod tracking_cache;
+pub(crate) mod window;
+pub(crate) mod watchers;
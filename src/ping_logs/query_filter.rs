@@ -0,0 +1,675 @@
+use crate::errors::ShowMeErrors;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+use std::iter::Peekable;
+use std::ops::Not;
+use std::str::{Chars, FromStr};
+
+/// A JSON pointer into a log record, kept as unescaped segments so `/` and `~` in a field name
+/// can't change which field is addressed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct JsonPointer(Vec<String>);
+
+impl From<&str> for JsonPointer {
+  /// Reads pointer syntax, e.g. `/payload/entries/info/treeName`; the leading `/` is optional.
+  fn from(pointer: &str) -> Self {
+    JsonPointer(
+      pointer
+        .trim_start_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect(),
+    )
+  }
+}
+
+impl JsonPointer {
+  /// The field the pointer ends at, e.g. `principal` for `/payload/principal`.
+  pub(crate) fn field(&self) -> Option<&str> {
+    self.0.last().map(String::as_str)
+  }
+
+  /// Every value the pointer reaches; like CREST, arrays on the way are searched element-wise, so
+  /// `/payload/entries/info/treeName` matches any entry's tree name.
+  fn resolve<'a>(&self, record: &'a Value) -> Vec<&'a Value> {
+    let mut values = vec![record];
+    for segment in &self.0 {
+      values = values
+        .into_iter()
+        .flat_map(|value| match value {
+          Value::Array(items) => items.iter().collect(),
+          value => vec![value],
+        })
+        .filter_map(|value| value.get(segment))
+        .collect();
+    }
+    values
+      .into_iter()
+      .flat_map(|value| match value {
+        Value::Array(items) => items.iter().collect(),
+        value => vec![value],
+      })
+      .collect()
+  }
+}
+
+impl Display for JsonPointer {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    for segment in &self.0 {
+      write!(f, "/{}", segment.replace('~', "~0").replace('/', "~1"))?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Literal {
+  String(String),
+  Number(serde_json::Number),
+  Bool(bool),
+}
+
+impl From<&str> for Literal {
+  fn from(value: &str) -> Self {
+    Literal::String(value.to_string())
+  }
+}
+
+impl From<String> for Literal {
+  fn from(value: String) -> Self {
+    Literal::String(value)
+  }
+}
+
+impl From<&String> for Literal {
+  fn from(value: &String) -> Self {
+    Literal::String(value.clone())
+  }
+}
+
+impl From<i64> for Literal {
+  fn from(value: i64) -> Self {
+    Literal::Number(value.into())
+  }
+}
+
+impl From<bool> for Literal {
+  fn from(value: bool) -> Self {
+    Literal::Bool(value)
+  }
+}
+
+impl Literal {
+  /// How `value` orders against the literal, `None` when their types can't be compared.
+  fn cmp_value(&self, value: &Value) -> Option<Ordering> {
+    match (self, value) {
+      (Literal::String(literal), Value::String(value)) => Some(value.as_str().cmp(literal)),
+      (Literal::Number(literal), Value::Number(value)) => {
+        value.as_f64()?.partial_cmp(&literal.as_f64()?)
+      }
+      (Literal::Bool(literal), Value::Bool(value)) => Some(value.cmp(literal)),
+      _ => None,
+    }
+  }
+
+  fn matches(&self, operator: Operator, value: &Value) -> bool {
+    let ordering = || self.cmp_value(value);
+    match (operator, self, value) {
+      (Operator::Sw, Literal::String(literal), Value::String(value)) => value.starts_with(literal),
+      (Operator::Co, Literal::String(literal), Value::String(value)) => value.contains(literal),
+      (Operator::Sw | Operator::Co, _, _) => false,
+      (Operator::Eq, _, _) => ordering().is_some_and(Ordering::is_eq),
+      (Operator::Gt, _, _) => ordering().is_some_and(Ordering::is_gt),
+      (Operator::Ge, _, _) => ordering().is_some_and(Ordering::is_ge),
+      (Operator::Lt, _, _) => ordering().is_some_and(Ordering::is_lt),
+      (Operator::Le, _, _) => ordering().is_some_and(Ordering::is_le),
+    }
+  }
+}
+
+impl Display for Literal {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      // JSON string quoting is what CREST expects, so quotes and backslashes come out escaped.
+      Literal::String(value) => write!(f, "{}", serde_json::Value::from(value.as_str())),
+      Literal::Number(value) => write!(f, "{value}"),
+      Literal::Bool(value) => write!(f, "{value}"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Operator {
+  Eq,
+  Sw,
+  Co,
+  Gt,
+  Ge,
+  Lt,
+  Le,
+}
+
+impl Operator {
+  fn parse(word: &str) -> Option<Self> {
+    match word {
+      "eq" => Some(Operator::Eq),
+      "sw" => Some(Operator::Sw),
+      "co" => Some(Operator::Co),
+      "gt" => Some(Operator::Gt),
+      "ge" => Some(Operator::Ge),
+      "lt" => Some(Operator::Lt),
+      "le" => Some(Operator::Le),
+      _ => None,
+    }
+  }
+}
+
+impl Display for Operator {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Operator::Eq => "eq",
+      Operator::Sw => "sw",
+      Operator::Co => "co",
+      Operator::Gt => "gt",
+      Operator::Ge => "ge",
+      Operator::Lt => "lt",
+      Operator::Le => "le",
+    })
+  }
+}
+
+/// A `_queryFilter` expression. Build one with the constructors and render it with `to_string()`,
+/// or parse one sent by the UI with `str::parse` to reject anything the logs API would choke on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum QueryFilter {
+  True,
+  False,
+  Compare {
+    pointer: JsonPointer,
+    operator: Operator,
+    value: Literal,
+  },
+  Present(JsonPointer),
+  And(Vec<QueryFilter>),
+  Or(Vec<QueryFilter>),
+  Not(Box<QueryFilter>),
+}
+
+impl QueryFilter {
+  fn compare(pointer: &str, operator: Operator, value: impl Into<Literal>) -> Self {
+    QueryFilter::Compare {
+      pointer: pointer.into(),
+      operator,
+      value: value.into(),
+    }
+  }
+
+  pub(crate) fn eq(pointer: &str, value: impl Into<Literal>) -> Self {
+    QueryFilter::compare(pointer, Operator::Eq, value)
+  }
+
+  pub(crate) fn sw(pointer: &str, value: impl Into<Literal>) -> Self {
+    QueryFilter::compare(pointer, Operator::Sw, value)
+  }
+
+  #[allow(dead_code)]
+  pub(crate) fn co(pointer: &str, value: impl Into<Literal>) -> Self {
+    QueryFilter::compare(pointer, Operator::Co, value)
+  }
+
+  #[allow(dead_code)]
+  pub(crate) fn gt(pointer: &str, value: impl Into<Literal>) -> Self {
+    QueryFilter::compare(pointer, Operator::Gt, value)
+  }
+
+  #[allow(dead_code)]
+  pub(crate) fn lt(pointer: &str, value: impl Into<Literal>) -> Self {
+    QueryFilter::compare(pointer, Operator::Lt, value)
+  }
+
+  pub(crate) fn pr(pointer: &str) -> Self {
+    QueryFilter::Present(pointer.into())
+  }
+
+  /// Flattens nested `and`s so chained calls render without redundant parentheses.
+  pub(crate) fn and(self, other: QueryFilter) -> Self {
+    match self {
+      QueryFilter::And(mut filters) => {
+        filters.push(other);
+        QueryFilter::And(filters)
+      }
+      filter => QueryFilter::And(vec![filter, other]),
+    }
+  }
+
+  pub(crate) fn or(self, other: QueryFilter) -> Self {
+    match self {
+      QueryFilter::Or(mut filters) => {
+        filters.push(other);
+        QueryFilter::Or(filters)
+      }
+      filter => QueryFilter::Or(vec![filter, other]),
+    }
+  }
+
+  /// Evaluates the filter against one log record, for stand-ins of the logs API.
+  pub(crate) fn matches(&self, record: &Value) -> bool {
+    match self {
+      QueryFilter::True => true,
+      QueryFilter::False => false,
+      QueryFilter::Compare {
+        pointer,
+        operator,
+        value,
+      } => pointer
+        .resolve(record)
+        .into_iter()
+        .any(|field| value.matches(*operator, field)),
+      QueryFilter::Present(pointer) => pointer
+        .resolve(record)
+        .into_iter()
+        .any(|field| !field.is_null()),
+      QueryFilter::And(filters) => filters.iter().all(|filter| filter.matches(record)),
+      QueryFilter::Or(filters) => filters.iter().any(|filter| filter.matches(record)),
+      QueryFilter::Not(filter) => !filter.matches(record),
+    }
+  }
+
+  /// Binding strength, used to decide where the rendered expression needs parentheses.
+  fn precedence(&self) -> u8 {
+    match self {
+      QueryFilter::Or(_) => 0,
+      QueryFilter::And(_) => 1,
+      _ => 2,
+    }
+  }
+
+  fn fmt_operand(&self, f: &mut Formatter<'_>, parent: u8) -> fmt::Result {
+    if self.precedence() <= parent {
+      write!(f, "({self})")
+    } else {
+      write!(f, "{self}")
+    }
+  }
+}
+
+impl Display for QueryFilter {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      QueryFilter::True => f.write_str("true"),
+      QueryFilter::False => f.write_str("false"),
+      QueryFilter::Compare {
+        pointer,
+        operator,
+        value,
+      } => write!(f, "{pointer} {operator} {value}"),
+      QueryFilter::Present(pointer) => write!(f, "{pointer} pr"),
+      QueryFilter::And(filters) | QueryFilter::Or(filters) => {
+        let joiner = if matches!(self, QueryFilter::And(_)) {
+          " and "
+        } else {
+          " or "
+        };
+        for (i, filter) in filters.iter().enumerate() {
+          if i > 0 {
+            f.write_str(joiner)?;
+          }
+          filter.fmt_operand(f, self.precedence())?;
+        }
+        Ok(())
+      }
+      QueryFilter::Not(filter) => write!(f, "!({filter})"),
+    }
+  }
+}
+
+impl Not for QueryFilter {
+  type Output = QueryFilter;
+
+  fn not(self) -> Self::Output {
+    QueryFilter::Not(Box::new(self))
+  }
+}
+
+impl From<QueryFilter> for String {
+  fn from(filter: QueryFilter) -> Self {
+    filter.to_string()
+  }
+}
+
+impl TryFrom<String> for QueryFilter {
+  type Error = ShowMeErrors;
+
+  fn try_from(filter: String) -> Result<Self, Self::Error> {
+    filter.parse()
+  }
+}
+
+impl FromStr for QueryFilter {
+  type Err = ShowMeErrors;
+
+  fn from_str(filter: &str) -> Result<Self, Self::Err> {
+    let mut parser = Parser {
+      tokens: tokenize(filter)?,
+      position: 0,
+      depth: 0,
+    };
+    let parsed = parser.or()?;
+    match parser.next() {
+      None => Ok(parsed),
+      Some(token) => Err(invalid(format!("unexpected {token}"))),
+    }
+  }
+}
+
+fn invalid(reason: impl Into<String>) -> ShowMeErrors {
+  ShowMeErrors::InvalidQueryFilter(reason.into())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Open,
+  Close,
+  Bang,
+  Word(String),
+  Quoted(String),
+}
+
+impl Display for Token {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Token::Open => f.write_str("'('"),
+      Token::Close => f.write_str("')'"),
+      Token::Bang => f.write_str("'!'"),
+      Token::Word(word) => write!(f, "'{word}'"),
+      Token::Quoted(value) => write!(f, "string {}", serde_json::Value::from(value.as_str())),
+    }
+  }
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, ShowMeErrors> {
+  let mut tokens = vec![];
+  let mut chars = filter.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match c {
+      c if c.is_whitespace() => {}
+      '(' => tokens.push(Token::Open),
+      ')' => tokens.push(Token::Close),
+      '!' => tokens.push(Token::Bang),
+      '"' | '\'' => {
+        let mut value = String::new();
+        loop {
+          match chars.next() {
+            Some('\\') => value.push(unescape(&mut chars)?),
+            Some(end) if end == c => break,
+            Some(other) => value.push(other),
+            None => return Err(invalid("unterminated string")),
+          }
+        }
+        tokens.push(Token::Quoted(value));
+      }
+      c => {
+        let mut word = String::from(c);
+        while let Some(&next) = chars.peek() {
+          if next.is_whitespace() || matches!(next, '(' | ')' | '"' | '\'') {
+            break;
+          }
+          word.push(next);
+          chars.next();
+        }
+        tokens.push(Token::Word(word));
+      }
+    }
+  }
+  Ok(tokens)
+}
+
+/// Four hex digits of a `\uXXXX` escape.
+fn hex_escape(chars: &mut Peekable<Chars<'_>>) -> Result<u32, ShowMeErrors> {
+  let digits: String = chars.by_ref().take(4).collect();
+  if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+    return Err(invalid(format!("invalid escape \\u{digits}")));
+  }
+  u32::from_str_radix(&digits, 16).map_err(|_| invalid(format!("invalid escape \\u{digits}")))
+}
+
+/// Decodes the escape after a backslash: the JSON set `Literal` renders, plus any other
+/// character standing for itself, such as `\'` inside single quotes.
+fn unescape(chars: &mut Peekable<Chars<'_>>) -> Result<char, ShowMeErrors> {
+  let escaped = match chars.next() {
+    Some('b') => '\u{8}',
+    Some('f') => '\u{c}',
+    Some('n') => '\n',
+    Some('r') => '\r',
+    Some('t') => '\t',
+    Some('u') => {
+      let unit = hex_escape(chars)?;
+      // Characters outside the basic plane come as a surrogate pair, `\ud83d\ude00`.
+      let code = if (0xD800..0xDC00).contains(&unit) {
+        if chars.next() != Some('\\') || chars.next() != Some('u') {
+          return Err(invalid("unpaired surrogate escape"));
+        }
+        let low = hex_escape(chars)?;
+        if !(0xDC00..0xE000).contains(&low) {
+          return Err(invalid("unpaired surrogate escape"));
+        }
+        0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00)
+      } else {
+        unit
+      };
+      char::from_u32(code).ok_or_else(|| invalid("unpaired surrogate escape"))?
+    }
+    Some(escaped) => escaped,
+    None => return Err(invalid("unterminated escape")),
+  };
+  Ok(escaped)
+}
+
+/// How deep `!` and parentheses may nest; the filter comes straight from a query parameter.
+const MAX_NESTING: usize = 32;
+
+/// Recursive descent over `or` < `and` < `!`/parentheses/comparisons.
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+  depth: usize,
+}
+
+impl Parser {
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+    token
+  }
+
+  fn peek_word(&self, keyword: &str) -> bool {
+    matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word == keyword)
+  }
+
+  fn or(&mut self) -> Result<QueryFilter, ShowMeErrors> {
+    let mut filter = self.and()?;
+    while self.peek_word("or") {
+      self.position += 1;
+      filter = filter.or(self.and()?);
+    }
+    Ok(filter)
+  }
+
+  fn and(&mut self) -> Result<QueryFilter, ShowMeErrors> {
+    let mut filter = self.unary()?;
+    while self.peek_word("and") {
+      self.position += 1;
+      filter = filter.and(self.unary()?);
+    }
+    Ok(filter)
+  }
+
+  /// Runs `parse` one nesting level deeper, refusing to go past `MAX_NESTING`.
+  fn nested(
+    &mut self,
+    parse: impl FnOnce(&mut Self) -> Result<QueryFilter, ShowMeErrors>,
+  ) -> Result<QueryFilter, ShowMeErrors> {
+    if self.depth >= MAX_NESTING {
+      return Err(invalid(format!("nested deeper than {MAX_NESTING} levels")));
+    }
+    self.depth += 1;
+    let filter = parse(self);
+    self.depth -= 1;
+    filter
+  }
+
+  fn unary(&mut self) -> Result<QueryFilter, ShowMeErrors> {
+    match self.next() {
+      Some(Token::Bang) => Ok(!self.nested(Parser::unary)?),
+      Some(Token::Open) => {
+        let filter = self.nested(Parser::or)?;
+        match self.next() {
+          Some(Token::Close) => Ok(filter),
+          _ => Err(invalid("missing ')'")),
+        }
+      }
+      Some(Token::Word(word)) if word == "true" => Ok(QueryFilter::True),
+      Some(Token::Word(word)) if word == "false" => Ok(QueryFilter::False),
+      Some(Token::Word(pointer)) => self.comparison(&pointer),
+      Some(token) => Err(invalid(format!("expected a field, found {token}"))),
+      None => Err(invalid("unexpected end of filter")),
+    }
+  }
+
+  fn comparison(&mut self, pointer: &str) -> Result<QueryFilter, ShowMeErrors> {
+    let operator = match self.next() {
+      Some(Token::Word(word)) if word == "pr" => return Ok(QueryFilter::pr(pointer)),
+      Some(Token::Word(word)) => {
+        Operator::parse(&word).ok_or_else(|| invalid(format!("unknown operator '{word}'")))?
+      }
+      Some(token) => return Err(invalid(format!("expected an operator, found {token}"))),
+      None => return Err(invalid(format!("missing operator after '{pointer}'"))),
+    };
+
+    let value = match self.next() {
+      Some(Token::Quoted(value)) => Literal::String(value),
+      Some(Token::Word(word)) if word == "true" => Literal::Bool(true),
+      Some(Token::Word(word)) if word == "false" => Literal::Bool(false),
+      Some(Token::Word(word)) => word
+        .parse::<serde_json::Number>()
+        .map(Literal::Number)
+        .map_err(|_| invalid(format!("'{word}' is not a string, number or boolean")))?,
+      Some(token) => return Err(invalid(format!("expected a value, found {token}"))),
+      None => {
+        return Err(invalid(format!(
+          "missing value after '{pointer} {operator}'"
+        )));
+      }
+    };
+
+    Ok(QueryFilter::compare(pointer, operator, value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(filter: &str) -> QueryFilter {
+    filter.parse().unwrap()
+  }
+
+  #[test]
+  fn and_binds_tighter_than_or() {
+    assert_eq!(
+      parse("/a eq 1 or /b eq 2 and /c eq 3"),
+      QueryFilter::eq("/a", 1).or(QueryFilter::eq("/b", 2).and(QueryFilter::eq("/c", 3)))
+    );
+    assert_eq!(
+      parse("(/a eq 1 or /b eq 2) and /c eq 3"),
+      QueryFilter::eq("/a", 1)
+        .or(QueryFilter::eq("/b", 2))
+        .and(QueryFilter::eq("/c", 3))
+    );
+    assert_eq!(
+      parse("!/a pr and /b co \"x\""),
+      (!QueryFilter::pr("/a")).and(QueryFilter::co("/b", "x"))
+    );
+  }
+
+  #[test]
+  fn renders_parentheses_only_where_needed() {
+    let filter = QueryFilter::gt("/a", 1)
+      .or(QueryFilter::lt("/b", 2))
+      .and(QueryFilter::sw("/c", "x"))
+      .and(QueryFilter::eq("/d", true));
+    assert_eq!(
+      filter.to_string(),
+      "(/a gt 1 or /b lt 2) and /c sw \"x\" and /d eq true"
+    );
+    assert_eq!((!QueryFilter::pr("/a/b~1c")).to_string(), "!(/a/b~1c pr)");
+  }
+
+  #[test]
+  fn decodes_json_escapes() {
+    assert_eq!(
+      parse(r#"/a eq "q\"b\\n\n\r\t\b\f\u00e9\ud83d\ude00""#),
+      QueryFilter::eq("/a", "q\"b\\n\n\r\t\u{8}\u{c}é😀")
+    );
+    assert_eq!(parse(r"/a eq 'it\'s'"), QueryFilter::eq("/a", "it's"));
+    for bad in [
+      r#"/a eq "\u12""#,
+      r#"/a eq "\ud83d""#,
+      r#"/a eq "\"#,
+      r#"/a eq "x"#,
+    ] {
+      assert!(bad.parse::<QueryFilter>().is_err(), "{bad}");
+    }
+  }
+
+  #[test]
+  fn rejects_nesting_past_the_limit() {
+    let nested = |depth: usize| format!("{}/a pr{}", "(".repeat(depth), ")".repeat(depth));
+    assert!(nested(MAX_NESTING).parse::<QueryFilter>().is_ok());
+    assert!(nested(MAX_NESTING + 1).parse::<QueryFilter>().is_err());
+    assert!(
+      format!("{}/a pr", "!".repeat(MAX_NESTING + 1))
+        .parse::<QueryFilter>()
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn rejects_malformed_filters() {
+    for bad in [
+      "",
+      "/a",
+      "/a eq",
+      "/a like 1",
+      "/a eq nope",
+      "(/a pr",
+      "/a pr)",
+      "and",
+    ] {
+      assert!(bad.parse::<QueryFilter>().is_err(), "{bad}");
+    }
+  }
+
+  #[test]
+  fn display_parses_back_to_the_same_filter() {
+    let filters = [
+      QueryFilter::True,
+      !QueryFilter::False,
+      QueryFilter::eq("/payload/principal", "a \"quoted\" \\ name\r\n\t\u{1}é😀"),
+      QueryFilter::co("/payload/message", "risk"),
+      QueryFilter::gt("/payload/elapsedTime", 10).and(QueryFilter::lt("/payload/elapsedTime", 20)),
+      QueryFilter::eq("/a", 1)
+        .or(QueryFilter::eq("/b", false))
+        .and(!QueryFilter::pr("/c").or(QueryFilter::sw("/d~0e", "x"))),
+      QueryFilter::eq("/a", 1).and(QueryFilter::eq("/b", 2).and(QueryFilter::eq("/c", 3))),
+    ];
+    for filter in filters {
+      assert_eq!(parse(&filter.to_string()), filter, "{filter}");
+    }
+  }
+}
@@ -0,0 +1,335 @@
+use crate::errors::ShowMeErrors;
+use actix_web::rt::time::sleep;
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+const MAX_ATTEMPTS: u32 = 5;
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Longer than this and the caller gets `RateLimited` rather than a hung request.
+const MAX_BUDGET_WAIT: Duration = Duration::from_secs(60);
+
+/// What the last response said about the rate-limit window.
+#[derive(Default)]
+struct Budget {
+  remaining: Option<u64>,
+  reset: Option<DateTime<Utc>>,
+}
+
+/// Shared by every call to one host: bounds how many requests are in flight, waits out an
+/// exhausted `x-ratelimit-*` budget and retries 429s and 5xxs with jittered backoff.
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+  permits: Arc<Semaphore>,
+  budget: Arc<Mutex<Budget>>,
+}
+
+impl Default for RateLimiter {
+  fn default() -> Self {
+    RateLimiter {
+      permits: Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS)),
+      budget: Arc::new(Mutex::new(Budget::default())),
+    }
+  }
+}
+
+impl RateLimiter {
+  /// Only holds a slot while a request is in flight, so one throttled call sleeping out its
+  /// backoff doesn't hold up everyone else. Other 4xx responses are returned as they are, for the
+  /// caller to check.
+  pub(crate) async fn send(&self, request: RequestBuilder) -> Result<Response, ShowMeErrors> {
+    let mut attempt = 0;
+    loop {
+      self.wait_for_budget().await?;
+      let permit = self
+        .permits
+        .acquire()
+        .await
+        .map_err(|_| ShowMeErrors::RateLimited("request limiter closed".into()))?;
+
+      let Some(this_attempt) = request.try_clone() else {
+        // Streaming bodies can't be replayed, so they only get one go.
+        let response = request.send().await?;
+        self.observe(response.headers())?;
+        return Ok(response);
+      };
+
+      let delay = match this_attempt.send().await {
+        Ok(response) => {
+          self.observe(response.headers())?;
+          let status = response.status();
+          if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+            return Ok(response);
+          }
+
+          if attempt + 1 >= MAX_ATTEMPTS {
+            return match status {
+              StatusCode::TOO_MANY_REQUESTS => Err(ShowMeErrors::RateLimited(format!(
+                "still throttled after {MAX_ATTEMPTS} attempts"
+              ))),
+              _ => Err(response.error_for_status().unwrap_err().into()),
+            };
+          }
+
+          match retry_after(response.headers()) {
+            Some(wait) if wait > MAX_BUDGET_WAIT => {
+              return Err(ShowMeErrors::RateLimited(format!(
+                "asked to retry after {}s",
+                wait.as_secs()
+              )));
+            }
+            Some(wait) => wait,
+            None => backoff(attempt),
+          }
+        }
+        Err(err) if (err.is_timeout() || err.is_connect()) && attempt + 1 < MAX_ATTEMPTS => {
+          backoff(attempt)
+        }
+        Err(err) => return Err(err.into()),
+      };
+      drop(permit);
+
+      attempt += 1;
+      println!(
+        "Retrying Ping request in {}ms ({}/{}).",
+        delay.as_millis(),
+        attempt,
+        MAX_ATTEMPTS - 1
+      );
+      sleep(delay).await;
+    }
+  }
+
+  /// Holds the request back until the rate-limit window resets if the last response used it up.
+  async fn wait_for_budget(&self) -> Result<(), ShowMeErrors> {
+    let wait = {
+      let mut budget = self
+        .budget
+        .lock()
+        .map_err(|_| ShowMeErrors::SharedLocking("rate limit budget".into()))?;
+
+      match (budget.remaining, budget.reset) {
+        (Some(0), Some(reset)) if reset > Utc::now() => (reset - Utc::now()).to_std().ok(),
+        (Some(remaining), _) => {
+          budget.remaining = Some(remaining.saturating_sub(1));
+          None
+        }
+        _ => None,
+      }
+    };
+
+    let Some(wait) = wait else {
+      return Ok(());
+    };
+    if wait > MAX_BUDGET_WAIT {
+      return Err(ShowMeErrors::RateLimited(format!(
+        "budget exhausted for another {}s",
+        wait.as_secs()
+      )));
+    }
+
+    println!("Rate limit budget used up, waiting {}ms.", wait.as_millis());
+    sleep(wait).await;
+
+    *self
+      .budget
+      .lock()
+      .map_err(|_| ShowMeErrors::SharedLocking("rate limit budget".into()))? = Budget::default();
+    Ok(())
+  }
+
+  fn observe(&self, headers: &HeaderMap) -> Result<(), ShowMeErrors> {
+    let header = |name: &str| {
+      headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+
+    let Some(remaining) = header("x-ratelimit-remaining") else {
+      return Ok(());
+    };
+
+    let mut budget = self
+      .budget
+      .lock()
+      .map_err(|_| ShowMeErrors::SharedLocking("rate limit budget".into()))?;
+    budget.remaining = Some(remaining);
+    budget.reset = header("x-ratelimit-reset").and_then(reset_time);
+    Ok(())
+  }
+}
+
+/// `x-ratelimit-reset` is sent either as epoch seconds or as seconds until the reset.
+fn reset_time(reset: u64) -> Option<DateTime<Utc>> {
+  const EPOCH_THRESHOLD: u64 = 1_000_000_000;
+
+  if reset >= EPOCH_THRESHOLD {
+    DateTime::from_timestamp(reset as i64, 0)
+  } else {
+    Some(Utc::now() + chrono::Duration::seconds(reset as i64))
+  }
+}
+
+/// `Retry-After` as either delay seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+  let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+  match value.parse::<u64>() {
+    Ok(seconds) => Some(Duration::from_secs(seconds)),
+    Err(_) => DateTime::parse_from_rfc2822(value).ok().map(|at| {
+      (at.with_timezone(&Utc) - Utc::now())
+        .to_std()
+        .unwrap_or_default()
+    }),
+  }
+}
+
+/// Exponential backoff with up to 50% jitter, so parallel callers don't retry in lockstep.
+fn backoff(attempt: u32) -> Duration {
+  let base = BASE_BACKOFF
+    .saturating_mul(2u32.saturating_pow(attempt))
+    .min(MAX_BACKOFF);
+  let jitter = Utc::now().timestamp_subsec_nanos() as u64 % (base.as_millis() as u64 / 2 + 1);
+
+  base + Duration::from_millis(jitter)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::{App, HttpResponse, HttpServer, rt, web};
+  use reqwest::header::HeaderValue;
+  use std::net::TcpListener;
+  use std::sync::atomic::{AtomicU32, Ordering};
+
+  fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+      headers.insert(*name, HeaderValue::from_str(value).unwrap());
+    }
+    headers
+  }
+
+  /// A host answering every request with `status` and `Retry-After: 0`, and how often it was asked.
+  fn upstream(status: u16) -> (String, Arc<AtomicU32>) {
+    let hits = Arc::new(AtomicU32::new(0));
+    let counter = hits.clone();
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = HttpServer::new(move || {
+      let counter = counter.clone();
+      App::new().default_service(web::to(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+        async move {
+          HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap())
+            .insert_header(("retry-after", "0"))
+            .finish()
+        }
+      }))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    rt::spawn(server);
+    (url, hits)
+  }
+
+  #[test]
+  fn reads_retry_after_as_seconds_or_a_date() {
+    assert_eq!(
+      retry_after(&headers(&[("retry-after", " 120 ")])),
+      Some(Duration::from_secs(120))
+    );
+
+    let soon = (Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+    let wait = retry_after(&headers(&[("retry-after", &soon)])).unwrap();
+    assert!(wait > Duration::from_secs(28) && wait <= Duration::from_secs(30));
+
+    let past = (Utc::now() - chrono::Duration::seconds(30)).to_rfc2822();
+    assert_eq!(
+      retry_after(&headers(&[("retry-after", &past)])),
+      Some(Duration::ZERO)
+    );
+
+    assert_eq!(retry_after(&headers(&[("retry-after", "soon")])), None);
+    assert_eq!(retry_after(&headers(&[("retry-after", "-5")])), None);
+    assert_eq!(retry_after(&HeaderMap::new()), None);
+  }
+
+  #[test]
+  fn reads_the_reset_as_epoch_or_relative_seconds() {
+    assert_eq!(
+      reset_time(1_700_000_000),
+      DateTime::from_timestamp(1_700_000_000, 0)
+    );
+
+    let reset = reset_time(30).unwrap() - Utc::now();
+    assert!(reset > chrono::Duration::seconds(28) && reset <= chrono::Duration::seconds(30));
+  }
+
+  #[test]
+  fn backs_off_exponentially_up_to_the_cap() {
+    for attempt in 0..MAX_ATTEMPTS {
+      let base = BASE_BACKOFF * 2u32.pow(attempt);
+      let delay = backoff(attempt);
+      assert!(
+        delay >= base && delay <= base + base / 2,
+        "{attempt}: {delay:?}"
+      );
+    }
+    let delay = backoff(40);
+    assert!(delay >= MAX_BACKOFF && delay <= MAX_BACKOFF + MAX_BACKOFF / 2);
+  }
+
+  #[actix_web::test]
+  async fn spends_the_budget_and_refuses_a_long_wait() {
+    let limiter = RateLimiter::default();
+    limiter
+      .observe(&headers(&[("x-ratelimit-remaining", "2")]))
+      .unwrap();
+    limiter.wait_for_budget().await.unwrap();
+    limiter.wait_for_budget().await.unwrap();
+    assert_eq!(limiter.budget.lock().unwrap().remaining, Some(0));
+
+    // Used up, but with no reset time to wait for.
+    limiter.wait_for_budget().await.unwrap();
+
+    limiter
+      .observe(&headers(&[
+        ("x-ratelimit-remaining", "0"),
+        ("x-ratelimit-reset", "600"),
+      ]))
+      .unwrap();
+    assert!(matches!(
+      limiter.wait_for_budget().await,
+      Err(ShowMeErrors::RateLimited(_))
+    ));
+  }
+
+  #[actix_web::test]
+  async fn gives_up_after_the_last_attempt() {
+    let client = reqwest::Client::new();
+
+    let (url, hits) = upstream(429);
+    let result = RateLimiter::default().send(client.get(&url)).await;
+    assert!(matches!(result, Err(ShowMeErrors::RateLimited(_))));
+    assert_eq!(hits.load(Ordering::SeqCst), MAX_ATTEMPTS);
+
+    let (url, hits) = upstream(503);
+    assert!(RateLimiter::default().send(client.get(&url)).await.is_err());
+    assert_eq!(hits.load(Ordering::SeqCst), MAX_ATTEMPTS);
+
+    // Other errors are the caller's to check, and aren't retried.
+    let (url, hits) = upstream(404);
+    let response = RateLimiter::default().send(client.get(&url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+  }
+}
@@ -1,15 +1,47 @@
 use crate::errors::ShowMeErrors;
-use crate::ping_logs::logs::{get_logs, Level, Logs};
+use crate::ping_logs::client::LogQuery;
+use crate::ping_logs::logs::{get_logs, search_logs, stream_logs, Level, LogPaging, Logs, ResultingLog};
+use crate::ping_logs::query_filter::QueryFilter;
+use crate::ping_logs::watchers::{Watch, WatchTarget};
+use crate::ping_logs::window::TimeWindow;
 use crate::{AppMutState };
 use actix_web::web::Query;
-use actix_web::{get, post, web, Responder};
-use reqwest::Client;
+use actix_web::{delete, get, post, rt, web, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use futures::{future, Stream, StreamExt, TryStreamExt};
 use serde::Deserialize;
 
 
 #[derive(Debug, Deserialize, Clone)]
 struct WatchFr {
-  fr_id: String,
+  id: Option<String>,
+  fr_id: Option<String>,
+  query_filter: Option<String>,
+  filters: Option<Filters>,
+  ttl_minutes: Option<i64>,
+}
+
+impl WatchFr {
+  fn target(&self) -> Result<WatchTarget, ShowMeErrors> {
+    match (&self.fr_id, &self.query_filter) {
+      (Some(fr_id), None) => Ok(WatchTarget::TransactionId(fr_id.clone())),
+      (None, Some(query_filter)) => Ok(WatchTarget::QueryFilter(query_filter.parse()?)),
+      _ => Err(ShowMeErrors::InvalidWatch(
+        "exactly one of fr_id or query_filter is required".into(),
+      )),
+    }
+  }
+
+  fn ttl(&self) -> Result<Option<chrono::Duration>, ShowMeErrors> {
+    self
+      .ttl_minutes
+      .map(|minutes| {
+        chrono::Duration::try_minutes(minutes)
+          .filter(|ttl| ttl > &chrono::Duration::zero())
+          .ok_or_else(|| ShowMeErrors::InvalidWatch(format!("ttl_minutes {minutes}")))
+      })
+      .transpose()
+  }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -21,6 +53,40 @@ enum Filters {
   Default,
 }
 
+impl Filters {
+  fn level(&self) -> Option<Level> {
+    match self {
+      Filters::Warn => Some(Level::Warning),
+      Filters::Error => Some(Level::Error),
+      Filters::Debug => Some(Level::Debug),
+      _ => None,
+    }
+  }
+}
+
+/// Drains a log stream page by page, dropping records that don't match the level filter as they arrive.
+async fn collect_logs(
+  records: impl Stream<Item = Result<ResultingLog, ShowMeErrors>>,
+  level: Option<Level>,
+) -> Result<Logs, ShowMeErrors> {
+  let mut result: Vec<ResultingLog> = records
+    .try_filter(|log| future::ready(level.as_ref().is_none_or(|level| log.payload.level() == Some(level))))
+    .try_collect()
+    .await?;
+
+  result.sort_by_key(|log| log.timestamp);
+
+  Ok(Logs::from(result))
+}
+
+/// Scripted decision nodes log under a logger named after the script id.
+fn script_logger_filter(script_id: &str) -> QueryFilter {
+  QueryFilter::sw(
+    "/payload/logger",
+    format!("scripts.AUTHENTICATION_TREE_DECISION_NODE.{script_id}"),
+  )
+}
+
 #[derive(Deserialize)]
 struct ScriptLogs {
   fr_id: String,
@@ -31,24 +97,19 @@ struct ScriptLogs {
 async fn script_logs(
   path: web::Path<ScriptLogs>,
   query: Query<LogsRequest>,
+  data: web::Data<AppMutState>,
 ) -> Result<web::Json<Logs>, ShowMeErrors> {
-  let formatted_query = format!(
-    "/payload/logger sw \"scripts.AUTHENTICATION_TREE_DECISION_NODE.{}\"",
-    path.script_id
-  );
+  let log_query = LogQuery::transaction(&path.fr_id)
+    .filter(script_logger_filter(&path.script_id))
+    .window(query.window()?);
 
-  let query_filter = Some(formatted_query.as_str());
-
-  match get_logs(&Client::new(), &path.fr_id, query_filter).await {
-    Ok(ll) => Ok(match query.filters.clone() {
-      None => web::Json(ll),
-      Some(filter) => match filter {
-        Filters::Warn => web::Json(ll.filter_logs(Level::Warning)),
-        Filters::Error => web::Json(ll.filter_logs(Level::Error)),
-        Filters::Debug => web::Json(ll.filter_logs(Level::Debug)),
-        _ => web::Json(ll),
-      },
-    }),
+  match collect_logs(
+    stream_logs(&data.log_client, log_query, query.paging()),
+    query.level(),
+  )
+  .await
+  {
+    Ok(ll) => Ok(web::Json(ll)),
     Err(err) => {
       println!("{}", err);
       Err(ShowMeErrors::NoLogsFound(path.fr_id.clone()))
@@ -61,45 +122,58 @@ struct LogsRequest {
   filters: Option<Filters>,
   script_id: Option<String>,
   script_name: Option<String>,
+  max_pages: Option<usize>,
+  max_records: Option<usize>,
+  begin_time: Option<String>,
+  end_time: Option<String>,
+}
+
+impl LogsRequest {
+  fn window(&self) -> Result<TimeWindow, ShowMeErrors> {
+    TimeWindow::parse(self.begin_time.as_deref(), self.end_time.as_deref())
+  }
+
+  fn paging(&self) -> LogPaging {
+    LogPaging::new(self.max_pages, self.max_records)
+  }
+
+  fn level(&self) -> Option<Level> {
+    self.filters.as_ref().and_then(Filters::level)
+  }
 }
 
 #[get("/{fr_id}")]
 async fn logs(
   fr_id: web::Path<String>,
   query: Query<LogsRequest>,
+  data: web::Data<AppMutState>,
 ) -> Result<web::Json<Logs>, ShowMeErrors> {
   let id = fr_id.into_inner();
 
-  let script_filter = query.script_id.clone().map(|script_id| {
-    format!(
-      "/payload/logger sw \"scripts.AUTHENTICATION_TREE_DECISION_NODE.{}\"",
-      script_id
-    )
-  });
+  let script_filter = query.script_id.as_deref().map(script_logger_filter);
 
   let node_filter = query
     .script_name
-    .clone()
-    .map(|script_name| format!("/payload/entries/info/displayName eq \"{}\"", script_name));
-
-  let defined_filters: Vec<String> = vec![node_filter, script_filter]
-    .iter()
-    .filter(|maybe_filter| maybe_filter.is_some())
-    .map(|filter| filter.as_ref().cloned().unwrap())
-    .collect();
+    .as_ref()
+    .map(|script_name| QueryFilter::eq("/payload/entries/info/displayName", script_name));
 
-  let query_filter = defined_filters.clone().join(" or ");
+  let log_query = match [node_filter, script_filter]
+    .into_iter()
+    .flatten()
+    .reduce(QueryFilter::or)
+  {
+    Some(query_filter) => LogQuery::transaction(&id).filter(query_filter),
+    None => LogQuery::transaction(&id),
+  }
+  .window(query.window()?);
 
-  match get_logs(&Client::new(), &id, Some(query_filter.as_str())).await {
-    Ok(ll) => Ok(match query.filters.clone() {
-      None => web::Json(ll),
-      Some(filter) => match filter {
-        Filters::Warn => web::Json(ll.filter_logs(Level::Warning)),
-        Filters::Error => web::Json(ll.filter_logs(Level::Error)),
-        Filters::Debug => web::Json(ll.filter_logs(Level::Debug)),
-        _ => web::Json(ll),
-      },
-    }),
+  match collect_logs(
+    stream_logs(&data.log_client, log_query, query.paging()),
+    query.level(),
+  )
+  .await
+  {
+    Ok(ll) => Ok(web::Json(ll)),
     Err(err) => {
       println!("{}", err);
       Err(ShowMeErrors::NoLogsFound(id))
@@ -107,55 +181,211 @@ async fn logs(
   }
 }
 
-#[get("/watch")]
+#[derive(Debug, Deserialize, Clone)]
+struct SearchRequest {
+  /// Comma separated, e.g. `am-access,idm-activity`; defaults to `am-everything,idm-everything`.
+  sources: Option<String>,
+  transaction_id: Option<String>,
+  query_filter: Option<String>,
+  /// Comma separated fields to project each record onto, e.g. `timestamp,payload/principal`.
+  fields: Option<String>,
+  max_pages: Option<usize>,
+  max_records: Option<usize>,
+  begin_time: Option<String>,
+  end_time: Option<String>,
+}
+
+pub(crate) fn comma_separated(list: Option<&str>) -> Vec<String> {
+  list
+    .unwrap_or_default()
+    .split(',')
+    .map(str::trim)
+    .filter(|item| !item.is_empty())
+    .map(str::to_string)
+    .collect()
+}
+
+impl SearchRequest {
+  fn log_query(&self) -> Result<LogQuery, ShowMeErrors> {
+    let log_query = match &self.transaction_id {
+      Some(transaction_id) => LogQuery::transaction(transaction_id),
+      None => LogQuery::all(),
+    };
+    let log_query = match &self.query_filter {
+      Some(query_filter) => log_query.filter(query_filter.parse()?),
+      None => log_query,
+    };
+
+    Ok(
+      log_query
+        .sources(comma_separated(self.sources.as_deref()))
+        .fields(comma_separated(self.fields.as_deref()))
+        .window(TimeWindow::parse(self.begin_time.as_deref(), self.end_time.as_deref())?),
+    )
+  }
+}
+
+/// Searches any log sources with an arbitrary `_queryFilter`, e.g. every error for one username.
+#[get("/search")]
+async fn search(
+  query: Query<SearchRequest>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Vec<serde_json::Value>>, ShowMeErrors> {
+  let mut records: Vec<serde_json::Value> = search_logs(
+    &data.log_client,
+    query.log_query()?,
+    LogPaging::new(query.max_pages, query.max_records),
+  )
+  .try_collect()
+  .await?;
+
+  // Projections may leave the timestamp out, in which case the API's order is kept.
+  records.sort_by(|a, b| a["timestamp"].as_str().cmp(&b["timestamp"].as_str()));
+
+  Ok(web::Json(records))
+}
+
+#[get("/sources")]
+async fn list_sources(data: web::Data<AppMutState>) -> Result<web::Json<Vec<String>>, ShowMeErrors> {
+  Ok(web::Json(data.log_client.sources().await?))
+}
+
+fn find_watch(data: &web::Data<AppMutState>, watch_id: &str) -> Result<Watch, ShowMeErrors> {
+  data
+    .watches
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("watches".into()))?
+    .get(watch_id)
+    .ok_or_else(|| ShowMeErrors::NoWatchFound(watch_id.to_string()))
+}
+
+#[get("/watch/{watch_id}")]
 async fn get_watch(
+  watch_id: web::Path<String>,
   data: web::Data<AppMutState>,
   query: Query<LogsRequest>,
 ) -> Result<web::Json<Logs>, ShowMeErrors> {
-  Ok(match data.transaction_id.lock() {
-    Ok(id) => match get_logs(&Client::new(), &*id, None).await {
-      Ok(ll) => match query.filters.clone() {
-        None => web::Json(ll),
-        Some(filter) => match filter {
-          Filters::Warn => web::Json(ll.filter_logs(Level::Warning)),
-          Filters::Error => web::Json(ll.filter_logs(Level::Error)),
-          Filters::Debug => web::Json(ll.filter_logs(Level::Debug)),
-          _ => web::Json(ll),
-        },
-      },
-      Err(err) => {
-        println!("{}", err);
-        web::Json(Logs::default())
-      }
+  let watch = find_watch(&data, &watch_id)?;
+  let log_query = match &watch.target {
+    WatchTarget::TransactionId(id) => LogQuery::transaction(id),
+    WatchTarget::QueryFilter(query_filter) => LogQuery::all().filter(query_filter.clone()),
+  };
+
+  let watched_logs = get_logs(&data.log_client, log_query.window(query.window()?), query.paging()).await;
+
+  Ok(match watched_logs {
+    Ok(ll) => match query.level().or(watch.level) {
+      None => web::Json(ll),
+      Some(level) => web::Json(ll.filter_logs(level)),
     },
-    _ => web::Json(Logs::default()),
+    Err(err) => {
+      println!("{}", err);
+      web::Json(Logs::default())
+    }
   })
 }
 
+/// Subscribes the socket to the server-side tail of a watch.
+#[get("/watch/{watch_id}/ws")]
+async fn watch_ws(
+  watch_id: web::Path<String>,
+  req: HttpRequest,
+  body: web::Payload,
+  data: web::Data<AppMutState>,
+  query: Query<LogsRequest>,
+) -> Result<HttpResponse, ShowMeErrors> {
+  let watch = find_watch(&data, &watch_id)?;
+  let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+
+  let subscriber_id = data
+    .tail_subscribers
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tail subscribers".into()))?
+    .subscribe(&watch.id, session.clone(), query.level());
+
+  rt::spawn(async move {
+    while let Some(Ok(message)) = messages.next().await {
+      let keep_open = match message {
+        Message::Ping(bytes) => session.pong(&bytes).await.is_ok(),
+        Message::Close(_) => false,
+        _ => true,
+      };
+
+      if !keep_open {
+        break;
+      }
+    }
+
+    if let Ok(mut subscribers) = data.tail_subscribers.lock() {
+      subscribers.unsubscribe(subscriber_id);
+    }
+    let _ = session.close(None).await;
+  });
+
+  Ok(response)
+}
+
 #[post("/watch")]
 async fn set_watch(
   data: web::Data<AppMutState>,
   payload: web::Json<WatchFr>,
-) -> Result<impl Responder, ShowMeErrors> {
-  let mut id = data
-    .transaction_id
+) -> Result<web::Json<Watch>, ShowMeErrors> {
+  let target = payload.target()?;
+  let ttl = payload.ttl()?;
+
+  let watch = data
+    .watches
     .lock()
-    .map_err(|_| ShowMeErrors::SharedLocking("transaction_id".into()))?;
-  *id = payload.fr_id.clone();
+    .map_err(|_| ShowMeErrors::SharedLocking("watches".into()))?
+    .create(
+      payload.id.clone(),
+      target,
+      payload.filters.as_ref().and_then(Filters::level),
+      ttl,
+    );
 
-  drop(id);
+  Ok(web::Json(watch))
+}
+
+#[get("/watches")]
+async fn list_watches(data: web::Data<AppMutState>) -> Result<web::Json<Vec<Watch>>, ShowMeErrors> {
+  let watches = data
+    .watches
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("watches".into()))?
+    .list();
+
+  Ok(web::Json(watches))
+}
 
-  Ok("success")
+#[delete("/watch/{watch_id}")]
+async fn delete_watch(
+  watch_id: web::Path<String>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Watch>, ShowMeErrors> {
+  let watch = data
+    .watches
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("watches".into()))?
+    .remove(&watch_id)
+    .ok_or_else(|| ShowMeErrors::NoWatchFound(watch_id.into_inner()))?;
+
+  Ok(web::Json(watch))
 }
 
 // this function could be located in a different module
 pub fn log_api(cfg: &mut web::ServiceConfig) {
   cfg.service(
     web::scope("/logs")
-      .service(script_logs)
-      .service(logs)
+      // Registered ahead of `/{fr_id}` so "watches" isn't taken for a transaction id.
+      .service(search)
+      .service(list_sources)
+      .service(list_watches)
       .service(get_watch)
-      .service(logs)
-      .service(set_watch),
+      .service(watch_ws)
+      .service(set_watch)
+      .service(delete_watch)
+      .service(script_logs)
+      .service(logs),
   );
 }
@@ -0,0 +1,471 @@
+use crate::ping_logs::logs::{CollectedLogs, ResultingLog};
+use crate::ping_logs::window::TimeWindow;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+const MAX_ENTRIES: usize = 1024;
+const ENTRY_TTL: Duration = Duration::from_secs(30 * 60);
+/// An entry fetched this recently is served from the cache without asking for a delta.
+const REFRESH_AFTER: Duration = Duration::from_secs(15);
+/// Records show up in the API a little after their timestamp, so a fetch with no end time only
+/// counts as covering up to this long before it finished.
+const INGESTION_MARGIN: TimeDelta = TimeDelta::seconds(60);
+
+struct Entry<V> {
+  value: V,
+  created: Instant,
+  last_used: Instant,
+}
+
+impl<V> Entry<V> {
+  fn new(value: V) -> Self {
+    let now = Instant::now();
+    Self {
+      value,
+      created: now,
+      last_used: now,
+    }
+  }
+
+  fn is_expired(&self) -> bool {
+    self.created.elapsed() > ENTRY_TTL
+  }
+}
+
+/// A map that drops entries past their TTL and evicts the least recently used when full.
+struct Lru<V> {
+  entries: HashMap<String, Entry<V>>,
+}
+
+impl<V> Default for Lru<V> {
+  fn default() -> Self {
+    Self {
+      entries: HashMap::new(),
+    }
+  }
+}
+
+impl<V> Lru<V> {
+  fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+    if self.entries.get(key).is_some_and(Entry::is_expired) {
+      self.entries.remove(key);
+    }
+
+    self.entries.get_mut(key).map(|entry| {
+      entry.last_used = Instant::now();
+      &mut entry.value
+    })
+  }
+
+  fn insert(&mut self, key: String, value: V) -> &mut V {
+    if !self.entries.contains_key(&key) && self.entries.len() >= MAX_ENTRIES {
+      self.entries.retain(|_, entry| !entry.is_expired());
+    }
+
+    if !self.entries.contains_key(&key)
+      && self.entries.len() >= MAX_ENTRIES
+      && let Some(oldest) = self
+        .entries
+        .iter()
+        .min_by_key(|(_, entry)| entry.last_used)
+        .map(|(key, _)| key.clone())
+    {
+      self.entries.remove(&oldest);
+    }
+
+    &mut self
+      .entries
+      .entry(key)
+      .insert_entry(Entry::new(value))
+      .into_mut()
+      .value
+  }
+
+  fn len(&self) -> usize {
+    self.entries.len()
+  }
+}
+
+/// True when every instant of `inner` is also in `outer`.
+fn covers(outer: &TimeWindow, inner: &TimeWindow) -> bool {
+  outer.begin_time <= inner.begin_time
+    && match (outer.end_time, inner.end_time) {
+      (None, _) => true,
+      (Some(_), None) => false,
+      (Some(outer), Some(inner)) => inner <= outer,
+    }
+}
+
+/// The tracking ids a transaction was linked to over one window.
+struct Links {
+  tracking_ids: Vec<String>,
+  window: TimeWindow,
+  fetched: Instant,
+}
+
+/// The span a tracking id's records have all been fetched for; `begin` is `None` when it
+/// reaches back as far as the API does.
+#[derive(Clone, Copy)]
+struct Covered {
+  begin: Option<DateTime<Utc>>,
+  end: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct TrackedLogs {
+  logs: Vec<ResultingLog>,
+  keys: HashSet<String>,
+  covered: Option<Covered>,
+  fetched: Option<Instant>,
+}
+
+/// What a caller should fetch for a tracking id, given what is already cached.
+pub(crate) enum TrackingFetch {
+  /// Nothing cached (or it expired): fetch the caller's whole window.
+  Full,
+  /// Only these parts of the caller's window are missing, each touching what is cached.
+  Gaps(Vec<TimeWindow>),
+  /// The whole window is cached and recent enough, serve straight from the cache.
+  Fresh,
+}
+
+impl TrackingFetch {
+  /// The windows to ask the API for, none when the cache has everything.
+  pub(crate) fn windows(&self, window: TimeWindow) -> Vec<TimeWindow> {
+    match self {
+      TrackingFetch::Full => vec![window],
+      TrackingFetch::Gaps(gaps) => gaps.clone(),
+      TrackingFetch::Fresh => vec![],
+    }
+  }
+}
+
+#[derive(Serialize, Default, Clone, Copy)]
+pub(crate) struct TrackingCacheStats {
+  hits: u64,
+  misses: u64,
+  transactions: usize,
+  tracking_ids: usize,
+}
+
+/// Remembers which tracking ids each transaction is linked to, and the outcome logs already
+/// fetched per tracking id, so repeated flow renders only ask the API for what's new.
+#[derive(Default)]
+pub(crate) struct TrackingCache {
+  transactions: Lru<Links>,
+  tracking: Lru<TrackedLogs>,
+  hits: u64,
+  misses: u64,
+}
+
+impl TrackingCache {
+  /// The tracking ids linked to `transaction_id`, unless they were looked up over less than
+  /// `window` or long enough ago that the login may have picked up new ones.
+  pub(crate) fn tracking_ids(
+    &mut self,
+    transaction_id: &str,
+    window: &TimeWindow,
+  ) -> Option<Vec<String>> {
+    let cached = self
+      .transactions
+      .get_mut(transaction_id)
+      .filter(|links| links.fetched.elapsed() < REFRESH_AFTER && covers(&links.window, window))
+      .map(|links| links.tracking_ids.clone());
+    self.count(cached.is_some());
+    cached
+  }
+
+  pub(crate) fn link(
+    &mut self,
+    transaction_id: &str,
+    window: TimeWindow,
+    tracking_ids: Vec<String>,
+  ) {
+    self.transactions.insert(
+      transaction_id.to_string(),
+      Links {
+        tracking_ids,
+        window,
+        fetched: Instant::now(),
+      },
+    );
+  }
+
+  pub(crate) fn plan_fetch(&mut self, tracking_id: &str, window: &TimeWindow) -> TrackingFetch {
+    let plan = match self.tracking.get_mut(tracking_id) {
+      Some(TrackedLogs {
+        covered: Some(covered),
+        fetched,
+        ..
+      }) => {
+        let mut gaps = vec![];
+        if window.begin_time < covered.begin {
+          gaps.push(TimeWindow {
+            begin_time: window.begin_time,
+            end_time: covered.begin,
+          });
+        }
+        let stale = fetched.is_none_or(|at| at.elapsed() >= REFRESH_AFTER);
+        if window.end_time.map_or(stale, |end| end > covered.end) {
+          gaps.push(TimeWindow {
+            begin_time: Some(covered.end),
+            end_time: window.end_time,
+          });
+        }
+
+        if gaps.is_empty() {
+          TrackingFetch::Fresh
+        } else {
+          TrackingFetch::Gaps(gaps)
+        }
+      }
+      _ => TrackingFetch::Full,
+    };
+    self.count(!matches!(plan, TrackingFetch::Full));
+    plan
+  }
+
+  /// Merges the records fetched over `windows` for a tracking id and returns everything cached
+  /// for it.
+  pub(crate) fn record(
+    &mut self,
+    tracking_id: &str,
+    windows: &[TimeWindow],
+    fetched: Option<CollectedLogs>,
+  ) -> Vec<ResultingLog> {
+    let tracked = match self.tracking.get_mut(tracking_id) {
+      Some(tracked) => tracked,
+      None => self
+        .tracking
+        .insert(tracking_id.to_string(), TrackedLogs::default()),
+    };
+
+    // Nothing fetched, because it failed or wasn't needed, leaves the entry as it was so a
+    // failure is retried next time.
+    let Some(fetched) = fetched else {
+      return tracked.logs.clone();
+    };
+
+    fetched.logs.into_iter().for_each(|log| {
+      if tracked.keys.insert(log.dedupe_key()) {
+        tracked.logs.push(log);
+      }
+    });
+    tracked.logs.sort_by_key(|log| log.timestamp);
+
+    // A capped fetch may have stopped anywhere in its window, so it covers none of it.
+    if !fetched.truncated {
+      let finished = Utc::now() - INGESTION_MARGIN;
+      for window in windows {
+        let end = window.end_time.unwrap_or(finished);
+        let end = window.begin_time.map_or(end, |begin| end.max(begin));
+        // Gaps always touch what is covered, so the union stays one span.
+        tracked.covered = Some(match tracked.covered {
+          Some(covered) => Covered {
+            begin: covered.begin.min(window.begin_time),
+            end: covered.end.max(end),
+          },
+          None => Covered {
+            begin: window.begin_time,
+            end,
+          },
+        });
+      }
+      tracked.fetched = Some(Instant::now());
+    }
+
+    tracked.logs.clone()
+  }
+
+  pub(crate) fn stats(&self) -> TrackingCacheStats {
+    TrackingCacheStats {
+      hits: self.hits,
+      misses: self.misses,
+      transactions: self.transactions.len(),
+      tracking_ids: self.tracking.len(),
+    }
+  }
+
+  fn count(&mut self, hit: bool) {
+    if hit {
+      self.hits += 1;
+    } else {
+      self.misses += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A window's begin and end, comparable where `TimeWindow` isn't.
+  type Span = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+  fn at(hour: u32) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(&format!("2024-05-01T{hour:02}:00:00Z"))
+      .unwrap()
+      .with_timezone(&Utc)
+  }
+
+  fn window(begin: u32, end: u32) -> TimeWindow {
+    TimeWindow {
+      begin_time: Some(at(begin)),
+      end_time: Some(at(end)),
+    }
+  }
+
+  fn fetched(messages: &[&str], truncated: bool) -> Option<CollectedLogs> {
+    let logs = messages
+      .iter()
+      .map(|message| {
+        serde_json::from_value(serde_json::json!({
+          "payload": message,
+          "timestamp": at(2).to_rfc3339(),
+          "type": "text/plain",
+        }))
+        .unwrap()
+      })
+      .collect();
+    Some(CollectedLogs { logs, truncated })
+  }
+
+  fn gaps(plan: TrackingFetch) -> Vec<Span> {
+    match plan {
+      TrackingFetch::Gaps(gaps) => gaps
+        .iter()
+        .map(|gap| (gap.begin_time, gap.end_time))
+        .collect(),
+      TrackingFetch::Full => panic!("expected gaps, got a full fetch"),
+      TrackingFetch::Fresh => panic!("expected gaps, got a cache hit"),
+    }
+  }
+
+  #[test]
+  fn covers_only_windows_inside_it() {
+    let open = TimeWindow {
+      begin_time: Some(at(2)),
+      end_time: None,
+    };
+
+    assert!(covers(&window(2, 6), &window(3, 5)));
+    assert!(covers(&window(2, 6), &window(2, 6)));
+    assert!(!covers(&window(2, 6), &window(1, 5)));
+    assert!(!covers(&window(2, 6), &window(3, 7)));
+    assert!(covers(&open, &window(3, 7)));
+    assert!(!covers(&window(2, 6), &open));
+    assert!(covers(&TimeWindow::default(), &window(3, 7)));
+    assert!(!covers(&window(2, 6), &TimeWindow::default()));
+  }
+
+  #[test]
+  fn plans_only_the_gaps_around_what_is_covered() {
+    let mut cache = TrackingCache::default();
+    assert!(matches!(
+      cache.plan_fetch("t", &window(2, 6)),
+      TrackingFetch::Full
+    ));
+
+    let logs = cache.record("t", &[window(2, 6)], fetched(&["a", "b"], false));
+    assert_eq!(logs.len(), 2);
+
+    assert!(matches!(
+      cache.plan_fetch("t", &window(3, 5)),
+      TrackingFetch::Fresh
+    ));
+    assert_eq!(
+      gaps(cache.plan_fetch("t", &window(1, 8))),
+      vec![(Some(at(1)), Some(at(2))), (Some(at(6)), Some(at(8)))]
+    );
+    assert_eq!(
+      gaps(cache.plan_fetch(
+        "t",
+        &TimeWindow {
+          begin_time: None,
+          end_time: Some(at(4)),
+        }
+      )),
+      vec![(None, Some(at(2)))]
+    );
+
+    // Filling a gap widens the coverage, and records seen before aren't kept twice.
+    let logs = cache.record("t", &[window(6, 8)], fetched(&["b", "c"], false));
+    assert_eq!(logs.len(), 3);
+    assert!(matches!(
+      cache.plan_fetch("t", &window(2, 8)),
+      TrackingFetch::Fresh
+    ));
+  }
+
+  #[test]
+  fn a_capped_fetch_leaves_coverage_untouched() {
+    let mut cache = TrackingCache::default();
+    cache.record("t", &[window(2, 6)], fetched(&["a"], true));
+    assert!(matches!(
+      cache.plan_fetch("t", &window(2, 6)),
+      TrackingFetch::Full
+    ));
+
+    cache.record("t", &[window(2, 6)], fetched(&["a"], false));
+    let logs = cache.record("t", &[window(6, 8)], fetched(&["b"], true));
+    assert_eq!(logs.len(), 2);
+    assert_eq!(
+      gaps(cache.plan_fetch("t", &window(2, 8))),
+      vec![(Some(at(6)), Some(at(8)))]
+    );
+
+    // A failed fetch neither covers nor drops anything.
+    assert_eq!(cache.record("t", &[window(6, 8)], None).len(), 2);
+    assert_eq!(
+      gaps(cache.plan_fetch("t", &window(2, 8))),
+      vec![(Some(at(6)), Some(at(8)))]
+    );
+  }
+
+  #[test]
+  fn evicts_the_least_recently_used_entry_when_full() {
+    let mut lru = Lru::default();
+    let start = Instant::now();
+    for i in 0..MAX_ENTRIES {
+      lru.insert(i.to_string(), i);
+      lru.entries.get_mut(&i.to_string()).unwrap().last_used =
+        start + Duration::from_millis(i as u64);
+    }
+    lru.entries.get_mut("0").unwrap().last_used = start + Duration::from_secs(60);
+
+    lru.insert("new".to_string(), MAX_ENTRIES);
+    assert_eq!(lru.len(), MAX_ENTRIES);
+    assert!(lru.get_mut("0").is_some());
+    assert!(lru.get_mut("1").is_none());
+    assert!(lru.get_mut("new").is_some());
+
+    // Re-inserting a key already there evicts nothing.
+    lru.insert("2".to_string(), 2);
+    assert_eq!(lru.len(), MAX_ENTRIES);
+  }
+
+  #[test]
+  fn drops_expired_entries_before_evicting_live_ones() {
+    let mut lru = Lru::default();
+    for i in 0..MAX_ENTRIES {
+      lru.insert(i.to_string(), i);
+    }
+    // The monotonic clock can't reach back past the TTL on a machine up for less than that.
+    let Some(expired) = Instant::now().checked_sub(ENTRY_TTL + Duration::from_secs(1)) else {
+      return;
+    };
+    lru.entries.get_mut("5").unwrap().created = expired;
+    lru.entries.get_mut("6").unwrap().created = expired;
+
+    lru.insert("new".to_string(), MAX_ENTRIES);
+    assert_eq!(lru.len(), MAX_ENTRIES - 1);
+    assert!(lru.get_mut("5").is_none());
+    assert!(
+      (0..MAX_ENTRIES)
+        .filter(|i| ![5, 6].contains(i))
+        .all(|i| lru.get_mut(&i.to_string()).is_some())
+    );
+  }
+}
@@ -0,0 +1,289 @@
+use crate::AppMutState;
+use crate::errors::ShowMeErrors;
+use crate::ping_logs::logs::{Level, ResultingLog};
+use crate::ping_logs::query_filter::QueryFilter;
+use actix_web::rt::time::sleep;
+use actix_web::web;
+use actix_ws::Session;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+const TAIL_POLL_SECS: u64 = 5;
+const MAX_SEEN_LOGS: usize = 10_000;
+
+const MAX_WATCHES: usize = 64;
+const DEFAULT_WATCH_TTL_HOURS: i64 = 24;
+const WATCH_IDLE_MINUTES: i64 = 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WatchTarget {
+  TransactionId(String),
+  QueryFilter(QueryFilter),
+}
+
+impl WatchTarget {
+  /// The tail API has no transaction id parameter, so transaction watches become a filter.
+  fn tail_filter(&self) -> QueryFilter {
+    match self {
+      WatchTarget::TransactionId(id) => QueryFilter::sw("/payload/transactionId", id),
+      WatchTarget::QueryFilter(query_filter) => query_filter.clone(),
+    }
+  }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct Watch {
+  pub(crate) id: String,
+  pub(crate) target: WatchTarget,
+  pub(crate) level: Option<Level>,
+  created: DateTime<Utc>,
+  expires: DateTime<Utc>,
+  last_accessed: DateTime<Utc>,
+}
+
+impl Watch {
+  fn is_stale(&self, now: DateTime<Utc>) -> bool {
+    self.expires <= now || now - self.last_accessed > ChronoDuration::minutes(WATCH_IDLE_MINUTES)
+  }
+}
+
+/// Named watches shared by everyone using the server, bounded so forgotten watches don't pile up.
+#[derive(Default)]
+pub(crate) struct WatchRegistry {
+  next_id: u64,
+  watches: HashMap<String, Watch>,
+}
+
+impl WatchRegistry {
+  /// Creates (or replaces) a watch, evicting stale ones and then the least recently used if full.
+  pub(crate) fn create(
+    &mut self,
+    id: Option<String>,
+    target: WatchTarget,
+    level: Option<Level>,
+    ttl: Option<ChronoDuration>,
+  ) -> Watch {
+    let now = Utc::now();
+    self.evict_stale(now);
+
+    let id = id.unwrap_or_else(|| {
+      self.next_id += 1;
+      format!("watch-{}", self.next_id)
+    });
+
+    if !self.watches.contains_key(&id)
+      && self.watches.len() >= MAX_WATCHES
+      && let Some(oldest) = self
+        .watches
+        .values()
+        .min_by_key(|watch| watch.last_accessed)
+        .map(|watch| watch.id.clone())
+    {
+      self.watches.remove(&oldest);
+    }
+
+    let watch = Watch {
+      id: id.clone(),
+      target,
+      level,
+      created: now,
+      expires: now + ttl.unwrap_or_else(|| ChronoDuration::hours(DEFAULT_WATCH_TTL_HOURS)),
+      last_accessed: now,
+    };
+    self.watches.insert(id, watch.clone());
+    watch
+  }
+
+  pub(crate) fn list(&mut self) -> Vec<Watch> {
+    self.evict_stale(Utc::now());
+    let mut watches: Vec<Watch> = self.watches.values().cloned().collect();
+    watches.sort_by_key(|watch| watch.created);
+    watches
+  }
+
+  /// Looks up a watch and marks it as used so it isn't evicted as idle.
+  pub(crate) fn get(&mut self, id: &str) -> Option<Watch> {
+    let now = Utc::now();
+    self.evict_stale(now);
+    self.watches.get_mut(id).map(|watch| {
+      watch.last_accessed = now;
+      watch.clone()
+    })
+  }
+
+  pub(crate) fn remove(&mut self, id: &str) -> Option<Watch> {
+    self.watches.remove(id)
+  }
+
+  fn evict_stale(&mut self, now: DateTime<Utc>) {
+    self.watches.retain(|_, watch| !watch.is_stale(now));
+  }
+}
+
+#[derive(Clone)]
+struct TailSubscriber {
+  id: u64,
+  watch_id: String,
+  session: Session,
+  level: Option<Level>,
+}
+
+#[derive(Default)]
+pub(crate) struct TailSubscribers {
+  next_id: u64,
+  subscribers: Vec<TailSubscriber>,
+}
+
+impl TailSubscribers {
+  pub(crate) fn subscribe(&mut self, watch_id: &str, session: Session, level: Option<Level>) -> u64 {
+    self.next_id += 1;
+    self.subscribers.push(TailSubscriber {
+      id: self.next_id,
+      watch_id: watch_id.to_string(),
+      session,
+      level,
+    });
+    self.next_id
+  }
+
+  pub(crate) fn unsubscribe(&mut self, id: u64) {
+    self.subscribers.retain(|subscriber| subscriber.id != id);
+  }
+}
+
+/// Remembers the most recent records pushed so overlapping tail pages aren't sent twice.
+#[derive(Default)]
+struct SeenLogs {
+  order: VecDeque<String>,
+  keys: HashSet<String>,
+}
+
+impl SeenLogs {
+  fn insert(&mut self, key: String) -> bool {
+    if !self.keys.insert(key.clone()) {
+      return false;
+    }
+
+    self.order.push_back(key);
+    if self.order.len() > MAX_SEEN_LOGS
+      && let Some(oldest) = self.order.pop_front()
+    {
+      self.keys.remove(&oldest);
+    }
+    true
+  }
+}
+
+struct TailCursor {
+  tail_filter: QueryFilter,
+  cookie: Option<String>,
+  seen: SeenLogs,
+}
+
+/// Tails every watch that has websocket subscribers and fans new records out to them.
+///
+/// Each watch keeps its own cookie; it is dropped once nobody is listening or the watch target changes.
+pub(crate) async fn tail_watches(data: web::Data<AppMutState>) -> Result<(), ShowMeErrors> {
+  let mut cursors: HashMap<String, TailCursor> = HashMap::new();
+
+  loop {
+    sleep(Duration::from_secs(TAIL_POLL_SECS)).await;
+
+    // A failed poll is logged and retried; returning would end the tail for every socket.
+    let mut by_watch: HashMap<String, Vec<TailSubscriber>> = HashMap::new();
+    match data.tail_subscribers.lock() {
+      Ok(tail_subscribers) => tail_subscribers.subscribers.iter().for_each(|subscriber| {
+        by_watch
+          .entry(subscriber.watch_id.clone())
+          .or_default()
+          .push(subscriber.clone())
+      }),
+      Err(_) => {
+        println!("{}", ShowMeErrors::SharedLocking("tail subscribers".into()));
+        continue;
+      }
+    }
+
+    cursors.retain(|watch_id, _| by_watch.contains_key(watch_id));
+
+    let mut closed = vec![];
+    for (watch_id, subscribers) in by_watch {
+      let watch = match data.watches.lock() {
+        Ok(mut watches) => watches.get(&watch_id),
+        Err(_) => {
+          println!("{}", ShowMeErrors::SharedLocking("watches".into()));
+          continue;
+        }
+      };
+
+      let Some(watch) = watch else {
+        // The watch was deleted or evicted, so its sockets have nothing left to receive.
+        for subscriber in subscribers {
+          closed.push(subscriber.id);
+          let _ = subscriber.session.close(None).await;
+        }
+        continue;
+      };
+
+      let tail_filter = watch.target.tail_filter();
+      let cursor = cursors.entry(watch_id).or_insert_with(|| TailCursor {
+        tail_filter: tail_filter.clone(),
+        cookie: None,
+        seen: SeenLogs::default(),
+      });
+      if cursor.tail_filter != tail_filter {
+        *cursor = TailCursor {
+          tail_filter,
+          cookie: None,
+          seen: SeenLogs::default(),
+        };
+      }
+
+      let page = match data.log_client.tail_page(&cursor.tail_filter, cursor.cookie.as_deref()).await {
+        Ok(page) => page,
+        Err(err) => {
+          println!("{}", err);
+          continue;
+        }
+      };
+
+      if let Some(next) = page.paged_results_cookie.filter(|next| !next.is_empty()) {
+        cursor.cookie = Some(next);
+      }
+
+      let fresh: Vec<ResultingLog> = page
+        .result
+        .into_iter()
+        .filter(|log| cursor.seen.insert(log.dedupe_key()))
+        .collect();
+
+      for mut subscriber in subscribers {
+        let level = subscriber.level.clone().or(watch.level.clone());
+        for log in fresh
+          .iter()
+          .filter(|log| level.as_ref().is_none_or(|level| log.payload.level() == Some(level)))
+        {
+          let text = match serde_json::to_string(log) {
+            Ok(text) => text,
+            Err(err) => {
+              println!("Could not serialize a tailed log: {}", err);
+              continue;
+            }
+          };
+          if subscriber.session.text(text).await.is_err() {
+            closed.push(subscriber.id);
+            break;
+          }
+        }
+      }
+    }
+
+    match data.tail_subscribers.lock() {
+      Ok(mut tail_subscribers) => closed.into_iter().for_each(|id| tail_subscribers.unsubscribe(id)),
+      Err(_) => println!("{}", ShowMeErrors::SharedLocking("tail subscribers".into())),
+    }
+  }
+}
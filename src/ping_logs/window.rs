@@ -0,0 +1,216 @@
+use crate::errors::ShowMeErrors;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+
+/// The monitoring API refuses ranges wider than this, so larger windows are split.
+pub(crate) const MAX_WINDOW_HOURS: i64 = 24;
+
+/// The finest time step the API resolves, which `query_params` renders to.
+const PRECISION: Duration = Duration::milliseconds(1);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeWindow {
+  pub begin_time: Option<DateTime<Utc>>,
+  pub end_time: Option<DateTime<Utc>>,
+}
+
+/// Accepts an RFC3339 timestamp, `now`, or a relative offset into the past such as `-30m`, `-2h`, `-1d`.
+fn parse_time(raw: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, ShowMeErrors> {
+  let raw = raw.trim();
+  if raw.eq_ignore_ascii_case("now") {
+    return Ok(now);
+  }
+
+  if let Some(relative) = raw.strip_prefix('-') {
+    let invalid = || ShowMeErrors::InvalidTimeWindow(raw.to_string());
+    // Split on a char boundary, so a multibyte unit is rejected rather than sliced through.
+    let (split, _) = relative.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = relative.split_at(split);
+    // Digits only: `parse` would also take `--5h` as five hours into the future.
+    if amount.is_empty() || !amount.bytes().all(|byte| byte.is_ascii_digit()) {
+      return Err(invalid());
+    }
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+
+    let offset = match unit {
+      "s" => Duration::try_seconds(amount),
+      "m" => Duration::try_minutes(amount),
+      "h" => Duration::try_hours(amount),
+      "d" => Duration::try_days(amount),
+      "w" => Duration::try_weeks(amount),
+      _ => None,
+    }
+    .ok_or_else(invalid)?;
+
+    return now.checked_sub_signed(offset).ok_or_else(invalid);
+  }
+
+  DateTime::parse_from_rfc3339(raw)
+    .map(|time| time.with_timezone(&Utc))
+    .map_err(|_| ShowMeErrors::InvalidTimeWindow(raw.to_string()))
+}
+
+impl TimeWindow {
+  pub fn parse(begin_time: Option<&str>, end_time: Option<&str>) -> Result<Self, ShowMeErrors> {
+    let now = Utc::now();
+    let window = TimeWindow {
+      begin_time: begin_time.map(|t| parse_time(t, now)).transpose()?,
+      end_time: end_time.map(|t| parse_time(t, now)).transpose()?,
+    };
+
+    match window {
+      TimeWindow {
+        begin_time: Some(begin),
+        end_time: Some(end),
+      } if begin > end => Err(ShowMeErrors::InvalidTimeWindow(format!(
+        "{} is after {}",
+        begin.to_rfc3339(),
+        end.to_rfc3339()
+      ))),
+      _ => Ok(window),
+    }
+  }
+
+  /// Breaks the window into consecutive, oldest-first chunks the API will accept.
+  ///
+  /// Both ends of a range are inclusive, so each chunk ends one step before the next begins;
+  /// otherwise a record on the boundary would come back twice.
+  pub fn split(&self) -> Vec<TimeWindow> {
+    let Some(begin) = self.begin_time else {
+      return vec![*self];
+    };
+    let end = self.end_time.unwrap_or_else(Utc::now);
+    let max = Duration::hours(MAX_WINDOW_HOURS);
+
+    if end - begin <= max {
+      return vec![*self];
+    }
+
+    let mut windows = vec![];
+    let mut cursor = begin;
+    loop {
+      let last = (cursor + max - PRECISION).min(end);
+      windows.push(TimeWindow {
+        begin_time: Some(cursor),
+        end_time: Some(last),
+      });
+      if last == end {
+        return windows;
+      }
+      cursor = last + PRECISION;
+    }
+  }
+
+  pub fn contains(&self, time: DateTime<Utc>) -> bool {
+    self.begin_time.is_none_or(|begin| begin <= time) && self.end_time.is_none_or(|end| time <= end)
+  }
+
+  pub fn query_params(&self) -> Vec<(&'static str, String)> {
+    [("beginTime", self.begin_time), ("endTime", self.end_time)]
+      .into_iter()
+      .filter_map(|(name, time)| {
+        time.map(|time| (name, time.to_rfc3339_opts(SecondsFormat::Millis, true)))
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(raw: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(raw)
+      .unwrap()
+      .with_timezone(&Utc)
+  }
+
+  #[test]
+  fn parses_now_relative_offsets_and_timestamps() {
+    let now = at("2024-05-01T12:00:00Z");
+
+    assert_eq!(parse_time("now", now).unwrap(), now);
+    assert_eq!(parse_time(" NOW ", now).unwrap(), now);
+    assert_eq!(parse_time("-30s", now).unwrap(), at("2024-05-01T11:59:30Z"));
+    assert_eq!(parse_time("-15m", now).unwrap(), at("2024-05-01T11:45:00Z"));
+    assert_eq!(parse_time("-2h", now).unwrap(), at("2024-05-01T10:00:00Z"));
+    assert_eq!(parse_time("-1d", now).unwrap(), at("2024-04-30T12:00:00Z"));
+    assert_eq!(parse_time("-1w", now).unwrap(), at("2024-04-24T12:00:00Z"));
+    assert_eq!(
+      parse_time("2024-05-01T14:00:00+02:00", now).unwrap(),
+      at("2024-05-01T12:00:00Z")
+    );
+  }
+
+  #[test]
+  fn rejects_malformed_times() {
+    let now = at("2024-05-01T12:00:00Z");
+
+    for raw in [
+      "",
+      "-",
+      "-h",
+      "-5",
+      "-5y",
+      "--5h",
+      "-+5h",
+      "-5é",
+      "-é",
+      "-99999999999999999999d",
+      "5h",
+      "yesterday",
+    ] {
+      assert!(parse_time(raw, now).is_err(), "{raw:?} should be rejected");
+    }
+  }
+
+  #[test]
+  fn rejects_a_window_that_ends_before_it_begins() {
+    assert!(TimeWindow::parse(Some("now"), Some("-1h")).is_err());
+
+    let window = TimeWindow::parse(Some("-1h"), Some("now")).unwrap();
+    assert_eq!(
+      window.end_time.unwrap() - window.begin_time.unwrap(),
+      Duration::hours(1)
+    );
+  }
+
+  #[test]
+  fn keeps_a_window_the_api_accepts_whole() {
+    let window = TimeWindow {
+      begin_time: Some(at("2024-05-01T00:00:00Z")),
+      end_time: Some(at("2024-05-02T00:00:00Z")),
+    };
+
+    let chunks = window.split();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].begin_time, window.begin_time);
+    assert_eq!(chunks[0].end_time, window.end_time);
+    assert_eq!(TimeWindow::default().split().len(), 1);
+  }
+
+  #[test]
+  fn splits_a_wide_window_into_chunks_that_do_not_overlap() {
+    let window = TimeWindow {
+      begin_time: Some(at("2024-05-01T00:00:00Z")),
+      end_time: Some(at("2024-05-03T06:00:00Z")),
+    };
+
+    let chunks = window.split();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[0].begin_time, window.begin_time);
+    assert_eq!(chunks[2].end_time, window.end_time);
+
+    for chunk in &chunks {
+      let (begin, end) = (chunk.begin_time.unwrap(), chunk.end_time.unwrap());
+      assert!(begin <= end);
+      assert!(end - begin <= Duration::hours(MAX_WINDOW_HOURS));
+    }
+    for pair in chunks.windows(2) {
+      let (end, next) = (pair[0].end_time.unwrap(), pair[1].begin_time.unwrap());
+      assert_eq!(next - end, PRECISION);
+      assert!(!pair[0].contains(next));
+      assert!(!pair[1].contains(end));
+      assert_ne!(pair[0].query_params()[1].1, pair[1].query_params()[0].1);
+    }
+  }
+}
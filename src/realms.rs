@@ -0,0 +1,76 @@
+use crate::AppMutState;
+use crate::errors::ShowMeErrors;
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest, get, web};
+use futures::future::{Ready, ready};
+use serde::Serialize;
+
+/// The AM config path segment for a realm, e.g. `realms/root/realms/alpha`.
+pub fn realm_path(realm: &str) -> String {
+  format!("realms/root/realms/{realm}")
+}
+
+/// The realm a request targets: the `{realm}` path segment, or the first configured realm
+/// for the unprefixed routes.
+pub struct Realm(pub String);
+
+impl FromRequest for Realm {
+  type Error = ShowMeErrors;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    let realms = req
+      .app_data::<web::Data<AppMutState>>()
+      .map(|data| data.realms.clone())
+      .unwrap_or_default();
+
+    ready(match req.match_info().get("realm") {
+      Some(realm) if realms.iter().any(|known| known == realm) => Ok(Realm(realm.to_string())),
+      Some(realm) => Err(ShowMeErrors::UnknownRealm(realm.to_string())),
+      None => realms
+        .first()
+        .cloned()
+        .map(Realm)
+        .ok_or_else(|| ShowMeErrors::UnknownRealm("no realms are configured".into())),
+    })
+  }
+}
+
+#[derive(Serialize)]
+struct RealmSummary {
+  realm: String,
+  trees: usize,
+  scripts: usize,
+}
+
+#[get("")]
+async fn list_realms(
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Vec<RealmSummary>>, ShowMeErrors> {
+  let scripts = data
+    .script_config
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("realm list".into()))?;
+  let trees = data
+    .authentication_tree
+    .read()
+    .map_err(|_| ShowMeErrors::SharedLocking("realm list".into()))?;
+
+  Ok(web::Json(
+    data
+      .realms
+      .iter()
+      .map(|realm| RealmSummary {
+        realm: realm.clone(),
+        trees: trees
+          .get(realm)
+          .map_or(0, |trees| trees.get_tree_list().len()),
+        scripts: scripts.get(realm).map_or(0, |scripts| scripts.len()),
+      })
+      .collect(),
+  ))
+}
+
+pub fn realms_api(cfg: &mut web::ServiceConfig) {
+  cfg.service(list_realms);
+}
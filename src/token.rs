@@ -1,16 +1,21 @@
+use crate::AppMutState;
 use crate::ShowMeErrors;
+use crate::config::{CredentialSource, EnvironmentConfig};
+use crate::ping_logs::client::LogClient;
+use actix_web::rt::time::sleep;
+use actix_web::web;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
 use jsonwebkey::JsonWebKey;
 use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
 use openssl::base64;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::ops::Add;
 use std::sync::Mutex;
+use std::time::Duration;
 
-#[derive(Serialize, Clone)]
-#[derive(Debug)]
-pub(crate) struct Payload {
+#[derive(Serialize, Debug)]
+struct Payload {
   iss: String,
   sub: String,
   aud: String,
@@ -18,151 +23,232 @@ pub(crate) struct Payload {
   jti: String,
 }
 
-fn update_exp(payload: Payload, exp: i64) -> Payload {
-  Payload { exp, ..payload }
-}
-
-pub struct Token {
-  pub exp_date: i64,
-  key: EncodingKey,
-  aud: String,
-  pub dom: String,
-}
-
 #[derive(Deserialize)]
 struct TokenResponse {
   access_token: String,
-  scope: String,
-  token_type: String,
   expires_in: i64,
 }
 
-const THREE_MIN: i64 = 3 * 60;
-const FIFTEEN_MIN: i64 = 899;
+/// An access token and the moment AM stops accepting it.
+#[derive(Clone)]
+struct AccessToken {
+  value: String,
+  issued_at: DateTime<Utc>,
+  expires_at: DateTime<Utc>,
+}
+
+impl AccessToken {
+  /// `REFRESH_AHEAD_SECS` before expiry, or halfway through the lifetime of short-lived tokens.
+  fn refresh_at(&self) -> DateTime<Utc> {
+    let ahead =
+      ChronoDuration::seconds(REFRESH_AHEAD_SECS).min((self.expires_at - self.issued_at) / 2);
+    self.expires_at - ahead
+  }
+}
+
+/// AIC rejects assertions that expire more than 15 minutes out.
+const ASSERTION_LIFETIME_SECS: i64 = 899;
+/// The background refresh swaps tokens this long before they expire.
+const REFRESH_AHEAD_SECS: i64 = 3 * 60;
+/// Callers never get a token closer to expiry than this, in case the refresh loop fell behind.
+const EXPIRY_MARGIN_SECS: i64 = 30;
+const REFRESH_RETRY: Duration = Duration::from_secs(30);
+/// Keeps a tenant handing out near-instantly expiring tokens from spinning the refresh loop.
+const MIN_REFRESH_WAIT: Duration = Duration::from_secs(5);
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(30);
 
 const CLIENT_ID: &str = "service-account";
 const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
-const SCOPE: &str = "fr:idm:* fr:am:*";
-const AUD_PART: &str = "am/oauth2/access_token";
 
-pub async fn get_usable_token(
-  token: &Token,
-  payload: &Mutex<Payload>,
-  existing_token: &Mutex<String>,
-) -> Result<(String, Payload), ShowMeErrors> {
-  let (need_new_exp, exp) = token.new_or_update_exp();
-  let mut locked_string = existing_token
-    .lock()
-    .map_err(|_| ShowMeErrors::SharedLocking("token".to_string()))?;
+/// What the manager signs assertions with, or the token it hands out unchanged.
+enum Credentials {
+  Assertion(EncodingKey),
+  Bearer(String),
+}
 
-  let locked_payload = payload.lock().map_err(|_| ShowMeErrors::SharedLocking("payload".to_string()))?.clone();
+impl Credentials {
+  fn load(source: &CredentialSource) -> Result<Self, ShowMeErrors> {
+    let jwk = |json: &str| -> Result<EncodingKey, ShowMeErrors> {
+      Ok(JsonWebKey::from_slice(json)?.key.to_encoding_key())
+    };
 
-  if !need_new_exp {
+    Ok(match source {
+      CredentialSource::JwkFile { path } => {
+        Credentials::Assertion(jwk(&fs::read_to_string(path)?)?)
+      }
+      CredentialSource::PemFile { path } => {
+        Credentials::Assertion(EncodingKey::from_rsa_pem(&fs::read(path)?)?)
+      }
+      CredentialSource::JwkEnv { var } => Credentials::Assertion(jwk(&std::env::var(var)?)?),
+      CredentialSource::Bearer { token } => Credentials::Bearer(token.clone()),
+    })
+  }
+}
 
-    Ok((locked_string.clone(), locked_payload))
+/// Exchanges signed service-account assertions for AM access tokens and caches the result.
+///
+/// Concurrent callers that find the token stale share one exchange rather than each minting their own.
+pub(crate) struct TokenManager {
+  pub(crate) dom: String,
+  service_account_id: String,
+  token_url: String,
+  aud: String,
+  scope: String,
+  credentials: Credentials,
+  client: LogClient,
+  current: Mutex<Option<AccessToken>>,
+  exchanging: tokio::sync::Mutex<()>,
+}
 
-  } else {
-    let old_payload = payload;
+impl TokenManager {
+  /// Exchanges go through `client`, so they share the environment's rate limiting.
+  pub(crate) fn new(config: &EnvironmentConfig, client: LogClient) -> Result<Self, ShowMeErrors> {
+    Ok(TokenManager {
+      dom: config.dom.clone(),
+      service_account_id: config.sa_id.clone(),
+      token_url: config.token_url(),
+      aud: config.audience(),
+      scope: config.scope(),
+      credentials: Credentials::load(&config.credentials()?)?,
+      client,
+      current: Mutex::new(None),
+      exchanging: tokio::sync::Mutex::new(()),
+    })
+  }
 
-    let new_payload = update_exp(locked_payload, exp);
+  /// A bearer token valid for at least `EXPIRY_MARGIN_SECS`, exchanging a new one if needed.
+  pub(crate) async fn access_token(&self) -> Result<String, ShowMeErrors> {
+    let key = match &self.credentials {
+      Credentials::Assertion(key) => key,
+      Credentials::Bearer(token) => return Ok(token.clone()),
+    };
 
-    let token_string = token_exchange(&new_payload, &*token.aud, &token.key)
-      .await
-      .unwrap_or_default();
+    let margin = ChronoDuration::seconds(EXPIRY_MARGIN_SECS);
+    if let Some(token) = self.cached(margin)? {
+      return Ok(token.value);
+    }
 
-    locked_string.clear();
-    locked_string.push_str(&*token_string);
+    let _exchanging = self.exchanging.lock().await;
+    // Whoever held the lock before us may have just refreshed it.
+    if let Some(token) = self.cached(margin)? {
+      return Ok(token.value);
+    }
+    Ok(self.exchange(key).await?.value)
+  }
 
-    Ok((token_string, new_payload))
+  /// False for pre-issued bearer tokens, which have nothing to refresh.
+  fn exchanges(&self) -> bool {
+    matches!(self.credentials, Credentials::Assertion(_))
   }
-}
 
-async fn token_exchange(
-  payload: &Payload,
-  aud: &str,
-  key: &jsonwebtoken::EncodingKey,
-) -> Result<String, ShowMeErrors> {
-  let header = Header {
-    alg: Algorithm::RS256,
-    ..Default::default()
-  };
-  let test = encode(&header, payload, key)?;
-
-  let client = reqwest::Client::new();
-  let mut token_data = HashMap::new();
-  token_data.insert("client_id".to_string(), CLIENT_ID.to_string());
-  token_data.insert("grant_type".to_string(), GRANT_TYPE.to_string());
-  token_data.insert("assertion".to_string(), test); // The JWT content
-  token_data.insert("scope".to_string(), SCOPE.to_string());
-
-  let token: TokenResponse = serde_json::from_slice(
-    &*client
-      .post(aud)
-      .form(&token_data)
-      .send()
-      .await?
-      .bytes()
-      .await?,
-  )?;
-
-  Ok(token.access_token)
-}
+  /// When the cached token should be replaced, `None` if there is no token yet.
+  fn refresh_due(&self) -> Result<Option<DateTime<Utc>>, ShowMeErrors> {
+    Ok(self.lock_current()?.as_ref().map(AccessToken::refresh_at))
+  }
 
-impl Token {
-  // ToDo this fn is a little silly.
-  fn new_or_update_exp(&self) -> (bool, i64) {
-    let cur = self.exp_date;
+  /// Replaces the token unless another caller already did while this one waited for the lock.
+  async fn refresh_ahead(&self) -> Result<(), ShowMeErrors> {
+    let Credentials::Assertion(key) = &self.credentials else {
+      return Ok(());
+    };
 
-    let now = chrono::Utc::now().timestamp();
-    if now.min(THREE_MIN).le(&cur) {
-      (true, now.add(FIFTEEN_MIN))
-    } else {
-      (false, cur)
+    let _exchanging = self.exchanging.lock().await;
+    if self.refresh_due()?.is_none_or(|due| due <= Utc::now()) {
+      self.exchange(key).await?;
     }
+    Ok(())
   }
-  pub async fn new() -> Result<(Self, Payload), ShowMeErrors> {
-    let service_account_id =
-      std::env::var("SA_ID").map_err(|_| ShowMeErrors::TokenDefault("SA_ID".to_string()))?;
-    let dom = std::env::var("DOM").map_err(|_| ShowMeErrors::TokenDefault("DOM".to_string()))?;
-    let aud = format!("{dom}/{AUD_PART}");
-    let exp = chrono::Utc::now().timestamp().min(FIFTEEN_MIN);
 
-    let mut buf = [0; 15];
-    openssl::rand::rand_bytes(&mut buf)?;
-    let jti = base64::encode_block(&buf);
+  fn cached(&self, margin: ChronoDuration) -> Result<Option<AccessToken>, ShowMeErrors> {
+    Ok(
+      self
+        .lock_current()?
+        .as_ref()
+        .filter(|token| token.expires_at - margin > Utc::now())
+        .cloned(),
+    )
+  }
 
-    let payload = Payload {
-      iss: service_account_id.clone(),
-      sub: service_account_id,
-      aud: aud.clone(),
-      exp,
-      jti,
-    };
+  fn lock_current(&self) -> Result<std::sync::MutexGuard<'_, Option<AccessToken>>, ShowMeErrors> {
+    self
+      .current
+      .lock()
+      .map_err(|_| ShowMeErrors::SharedLocking("access token".into()))
+  }
 
-    let key = JsonWebKey::from_slice(fs::read_to_string(std::env::var("KEY_FILE")?)?)?
-      .key
-      .to_encoding_key();
+  /// Signs a fresh assertion, with its own `jti`, and trades it for an access token.
+  async fn exchange(&self, key: &EncodingKey) -> Result<AccessToken, ShowMeErrors> {
+    let mut jti = [0; 15];
+    openssl::rand::rand_bytes(&mut jti)?;
 
+    let payload = Payload {
+      iss: self.service_account_id.clone(),
+      sub: self.service_account_id.clone(),
+      aud: self.aud.clone(),
+      exp: Utc::now().timestamp() + ASSERTION_LIFETIME_SECS,
+      jti: base64::encode_block(&jti),
+    };
     let header = Header {
       alg: Algorithm::RS256,
       ..Default::default()
     };
-    let test = encode(&header, &payload, &key)?;
 
     let mut token_data = HashMap::new();
-    token_data.insert("client_id".to_string(), CLIENT_ID.to_string());
-    token_data.insert("grant_type".to_string(), GRANT_TYPE.to_string());
-    token_data.insert("assertion".to_string(), test); // The JWT content
-    token_data.insert("scope".to_string(), SCOPE.to_string());
-
-    Ok((
-      Self {
-        exp_date: exp,
-        key,
-        aud,
-        dom,
-      },
-      payload,
-    ))
+    token_data.insert("client_id", CLIENT_ID.to_string());
+    token_data.insert("grant_type", GRANT_TYPE.to_string());
+    token_data.insert("assertion", encode(&header, &payload, key)?);
+    token_data.insert("scope", self.scope.clone());
+
+    let requested_at = Utc::now();
+    let response = self
+      .client
+      .send_unchecked(
+        self
+          .client
+          .http()
+          .post(&self.token_url)
+          .timeout(EXCHANGE_TIMEOUT)
+          .form(&token_data),
+      )
+      .await?;
+    let status = response.status();
+    let body = response.bytes().await?;
+    if !status.is_success() {
+      return Err(ShowMeErrors::TokenExchange(format!(
+        "{status}: {}",
+        String::from_utf8_lossy(&body)
+      )));
+    }
+
+    let response: TokenResponse = serde_json::from_slice(&body)?;
+    let token = AccessToken {
+      value: response.access_token,
+      issued_at: requested_at,
+      // Counted from the request, so a slow response can't make the token look fresher than it is.
+      expires_at: requested_at + ChronoDuration::seconds(response.expires_in),
+    };
+
+    *self.lock_current()? = Some(token.clone());
+    Ok(token)
+  }
+}
+
+/// Swaps an environment's access token shortly before it expires so requests rarely wait on an exchange.
+pub(crate) async fn refresh_tokens(data: web::Data<AppMutState>) {
+  if !data.token.exchanges() {
+    return;
+  }
+
+  loop {
+    let wait = match data.token.refresh_due() {
+      Ok(Some(due)) => (due - Utc::now()).to_std().unwrap_or_default(),
+      _ => Duration::ZERO,
+    };
+    sleep(wait.max(MIN_REFRESH_WAIT)).await;
+
+    if let Err(err) = data.token.refresh_ahead().await {
+      println!("Could not refresh the access token: {}", err);
+      sleep(REFRESH_RETRY).await;
+    }
   }
 }
@@ -0,0 +1,363 @@
+use crate::errors::ShowMeErrors;
+use crate::ping_logs::client::LogClient;
+use crate::trees::journeys::{NodeType, Tree};
+use crate::trees::nodes::{Script, node_snapshot};
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+const MAX_REVISIONS: usize = 50;
+/// How many node configs one snapshot fetches at a time.
+const MAX_CONCURRENT_NODES: usize = 4;
+
+/// A tree as it stood at one `_rev`, with the node configs and scripts it pointed at then.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct TreeSnapshot {
+  realm: String,
+  captured: DateTime<Utc>,
+  tree: Tree,
+  node_configs: HashMap<String, serde_json::Value>,
+  scripts: HashMap<String, Script>,
+  /// Nodes whose config couldn't be fetched, so there is nothing to compare them by.
+  #[serde(default)]
+  unavailable_nodes: BTreeSet<String>,
+}
+
+/// Fetches every node config (and script) of `tree` so later revisions have something to diff against.
+pub(crate) async fn capture_snapshot(
+  client: &LogClient,
+  tree: &Tree,
+  dom: &str,
+  token_str: &str,
+  realm: &str,
+) -> TreeSnapshot {
+  let fetched: Vec<_> = stream::iter(tree.nodes.iter())
+    .map(async |(node_id, node)| {
+      let snapshot = node_snapshot(client, &node.node_type, node_id, dom, token_str, realm)
+        .await
+        .inspect_err(|err| println!("Could not snapshot node [{}]: {}", node_id, err));
+      (node_id.clone(), snapshot)
+    })
+    .buffer_unordered(MAX_CONCURRENT_NODES)
+    .collect()
+    .await;
+
+  let mut node_configs = HashMap::new();
+  let mut scripts = HashMap::new();
+  let mut unavailable_nodes = BTreeSet::new();
+  for (node_id, snapshot) in fetched {
+    let Ok((config, script)) = snapshot else {
+      unavailable_nodes.insert(node_id);
+      continue;
+    };
+    if let Some(script) = script {
+      scripts.insert(node_id.clone(), script);
+    }
+    node_configs.insert(node_id, config);
+  }
+
+  TreeSnapshot {
+    realm: realm.to_string(),
+    captured: Utc::now(),
+    tree: tree.clone(),
+    node_configs,
+    scripts,
+    unavailable_nodes,
+  }
+}
+
+#[derive(Serialize)]
+pub(crate) struct RevisionSummary {
+  rev: String,
+  captured: DateTime<Utc>,
+  nodes: usize,
+}
+
+#[derive(Serialize)]
+struct NodeSummary {
+  node_id: String,
+  display_name: String,
+  node_type: NodeType,
+}
+
+#[derive(Serialize)]
+struct ConnectionChange {
+  node_id: String,
+  outcome: String,
+  from: Option<String>,
+  to: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ConfigChange {
+  node_id: String,
+  from: Option<serde_json::Value>,
+  to: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct ScriptChange {
+  node_id: String,
+  from: Option<Script>,
+  to: Option<Script>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct TreeDiff {
+  tree: String,
+  from: RevisionSummary,
+  to: RevisionSummary,
+  entry_node_changed: bool,
+  added_nodes: Vec<NodeSummary>,
+  removed_nodes: Vec<NodeSummary>,
+  changed_connections: Vec<ConnectionChange>,
+  changed_configs: Vec<ConfigChange>,
+  changed_scripts: Vec<ScriptChange>,
+  /// Nodes left out of the config and script comparison because either snapshot couldn't fetch them.
+  unavailable_nodes: Vec<String>,
+}
+
+impl TreeSnapshot {
+  /// `{realm}_{tree id}_{rev}.json`, each part encoded so sub-realms like `alpha/sub` stay one file.
+  fn file_name(&self) -> String {
+    format!(
+      "{}_{}_{}.json",
+      file_safe(&self.realm),
+      file_safe(&self.tree.id),
+      file_safe(self.tree.rev.trim_start_matches('-'))
+    )
+  }
+
+  fn summary(&self) -> RevisionSummary {
+    RevisionSummary {
+      rev: self.tree.rev.clone(),
+      captured: self.captured,
+      nodes: self.tree.nodes.len(),
+    }
+  }
+
+  fn node_summary(&self, node_id: &str) -> Option<NodeSummary> {
+    self.tree.nodes.get(node_id).map(|node| NodeSummary {
+      node_id: node_id.to_string(),
+      display_name: node.display_name.clone(),
+      node_type: node.node_type.clone(),
+    })
+  }
+
+  /// Everything that differs between `self` and the later snapshot `to`, ordered by node id.
+  fn diff(&self, to: &TreeSnapshot) -> TreeDiff {
+    let (old, new) = (&self.tree.nodes, &to.tree.nodes);
+    let node_ids: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let unavailable_nodes: BTreeSet<&String> = self
+      .unavailable_nodes
+      .iter()
+      .chain(&to.unavailable_nodes)
+      .collect();
+    let comparable = || {
+      node_ids
+        .iter()
+        .filter(|id| !unavailable_nodes.contains(**id))
+    };
+
+    let mut changed_connections = vec![];
+    for node_id in node_ids
+      .iter()
+      .filter(|id| old.contains_key(**id) && new.contains_key(**id))
+    {
+      let (before, after) = (&old[*node_id].connections, &new[*node_id].connections);
+      let outcomes: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+
+      changed_connections.extend(
+        outcomes
+          .into_iter()
+          .filter(|outcome| before.get(*outcome) != after.get(*outcome))
+          .map(|outcome| ConnectionChange {
+            node_id: node_id.to_string(),
+            outcome: outcome.clone(),
+            from: before.get(outcome).cloned(),
+            to: after.get(outcome).cloned(),
+          }),
+      );
+    }
+
+    let changed_configs = comparable()
+      .filter(|id| self.node_configs.get(**id) != to.node_configs.get(**id))
+      .map(|id| ConfigChange {
+        node_id: id.to_string(),
+        from: self.node_configs.get(*id).cloned(),
+        to: to.node_configs.get(*id).cloned(),
+      })
+      .collect();
+
+    let changed_scripts = comparable()
+      .filter(|id| self.scripts.get(**id) != to.scripts.get(**id))
+      .map(|id| ScriptChange {
+        node_id: id.to_string(),
+        from: self.scripts.get(*id).cloned(),
+        to: to.scripts.get(*id).cloned(),
+      })
+      .collect();
+
+    TreeDiff {
+      tree: to.tree.id.clone(),
+      from: self.summary(),
+      to: to.summary(),
+      entry_node_changed: self.tree.entry_node_id != to.tree.entry_node_id,
+      added_nodes: node_ids
+        .iter()
+        .filter(|id| !old.contains_key(**id))
+        .filter_map(|id| to.node_summary(id))
+        .collect(),
+      removed_nodes: node_ids
+        .iter()
+        .filter(|id| !new.contains_key(**id))
+        .filter_map(|id| self.node_summary(id))
+        .collect(),
+      changed_connections,
+      changed_configs,
+      changed_scripts,
+      unavailable_nodes: unavailable_nodes.into_iter().cloned().collect(),
+    }
+  }
+}
+
+/// `name` with everything but ASCII letters, digits and `-` percent-encoded, so it is safe as a
+/// single path component.
+fn file_safe(name: &str) -> String {
+  name
+    .bytes()
+    .map(|byte| match byte {
+      b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' => (byte as char).to_string(),
+      _ => format!("%{byte:02X}"),
+    })
+    .collect()
+}
+
+/// Snapshots of each tree per realm, oldest first, mirrored to a directory per environment under
+/// `TREE_HISTORY_DIR` when it is set.
+#[derive(Default)]
+pub(crate) struct TreeHistory {
+  dir: Option<PathBuf>,
+  trees: HashMap<(String, String), Vec<TreeSnapshot>>,
+}
+
+impl TreeHistory {
+  /// Starts from whatever an earlier run left in `environment`'s directory under
+  /// `TREE_HISTORY_DIR`, or empty without it.
+  pub(crate) fn load(environment: &str) -> Self {
+    let Ok(dir) = std::env::var("TREE_HISTORY_DIR").map(PathBuf::from) else {
+      return TreeHistory::default();
+    };
+    let dir = dir.join(file_safe(environment));
+
+    let mut history = TreeHistory {
+      dir: Some(dir.clone()),
+      ..TreeHistory::default()
+    };
+
+    let snapshots = fs::read_dir(&dir)
+      .into_iter()
+      .flatten()
+      .flatten()
+      .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+      .filter_map(|entry| {
+        fs::read(entry.path())
+          .ok()
+          .and_then(|bytes| serde_json::from_slice::<TreeSnapshot>(&bytes).ok())
+      });
+
+    for snapshot in snapshots {
+      history
+        .trees
+        .entry((snapshot.realm.clone(), snapshot.tree.id.clone()))
+        .or_default()
+        .push(snapshot);
+    }
+    history.trees.values_mut().for_each(|revisions| {
+      revisions.sort_by_key(|snapshot| snapshot.captured);
+    });
+
+    history
+  }
+
+  /// True when `tree` is at a `_rev` we have no snapshot for yet.
+  pub(crate) fn is_new_revision(&self, realm: &str, tree: &Tree) -> bool {
+    self
+      .trees
+      .get(&(realm.to_string(), tree.id.clone()))
+      .and_then(|revisions| revisions.last())
+      .is_none_or(|latest| latest.tree.rev != tree.rev)
+  }
+
+  pub(crate) fn record(&mut self, snapshot: TreeSnapshot) -> Result<(), ShowMeErrors> {
+    if let Some(dir) = &self.dir {
+      fs::create_dir_all(dir)?;
+      fs::write(
+        dir.join(snapshot.file_name()),
+        serde_json::to_vec(&snapshot)?,
+      )?;
+    }
+
+    let revisions = self
+      .trees
+      .entry((snapshot.realm.clone(), snapshot.tree.id.clone()))
+      .or_default();
+    revisions.push(snapshot);
+    if revisions.len() > MAX_REVISIONS {
+      let pruned = revisions.remove(0);
+      if let Some(dir) = &self.dir
+        && let Err(err) = fs::remove_file(dir.join(pruned.file_name()))
+      {
+        println!(
+          "Could not delete pruned snapshot [{}]: {}",
+          pruned.file_name(),
+          err
+        );
+      }
+    }
+    Ok(())
+  }
+
+  pub(crate) fn revisions(&self, realm: &str, name: &str) -> Vec<RevisionSummary> {
+    self
+      .trees
+      .get(&(realm.to_string(), name.to_string()))
+      .map(|revisions| revisions.iter().map(TreeSnapshot::summary).collect())
+      .unwrap_or_default()
+  }
+
+  /// Diffs two revisions of a tree; `to` defaults to the latest and `from` to the one before `to`.
+  pub(crate) fn diff(
+    &self,
+    realm: &str,
+    name: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+  ) -> Result<TreeDiff, ShowMeErrors> {
+    let revisions = self
+      .trees
+      .get(&(realm.to_string(), name.to_string()))
+      .ok_or_else(|| ShowMeErrors::NoRevisionFound(name.to_string()))?;
+
+    let position = |rev: &str| {
+      revisions
+        .iter()
+        .rposition(|snapshot| snapshot.tree.rev == rev)
+        .ok_or_else(|| ShowMeErrors::NoRevisionFound(format!("{name}@{rev}")))
+    };
+
+    let to = match to {
+      Some(rev) => position(rev)?,
+      None => revisions.len() - 1,
+    };
+    let from = match from {
+      Some(rev) => position(rev)?,
+      None => to.saturating_sub(1),
+    };
+
+    Ok(revisions[from].diff(&revisions[to]))
+  }
+}
@@ -1,4 +1,6 @@
 use crate::errors::ShowMeErrors;
+use crate::ping_logs::client::LogClient;
+use crate::realms::realm_path;
 use crate::trees::nodes::{node_id_to_script_config, NodeConfig, NodeData};
 use crate::NodeOutcomeEdge;
 use futures::future::JoinAll;
@@ -105,39 +107,39 @@ impl Display for NodeType {
   }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Node {
-  connections: HashMap<String, String>,
-  display_name: String,
-  node_type: NodeType,
+  pub(crate) connections: HashMap<String, String>,
+  pub(crate) display_name: String,
+  pub(crate) node_type: NodeType,
   x: Option<f32>,
   y: Option<f32>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct StaticNode {
   x: f32,
   y: f32,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Tree {
   #[serde(rename = "_id")]
-  id: String,
+  pub(crate) id: String,
   #[serde(rename = "_rev")]
-  rev: String,
+  pub(crate) rev: String,
   identity_resource: Option<String>,
-  entry_node_id: String,
+  pub(crate) entry_node_id: String,
   inner_tree_only: bool,
   no_session: bool,
   must_run: bool,
   enabled: bool,
   transaction_only: Option<bool>,
   ui_config: HashMap<String, String>,
-  nodes: HashMap<String, Node>,
+  pub(crate) nodes: HashMap<String, Node>,
   static_nodes: HashMap<String, StaticNode>,
 }
 
@@ -152,6 +154,29 @@ pub struct AuthenticationTreeList {
   remaining_paged_results: i16,
 }
 
+pub async fn get_authentication_trees(
+  client: &LogClient,
+  dom: &str,
+  token_str: &str,
+  realm: &str,
+) -> Result<AuthenticationTreeList, ShowMeErrors> {
+  let trees = &client
+    .send(
+      client
+        .http()
+        .get(format!(
+          "{dom}/am/json/{}/realm-config/authentication/authenticationtrees/trees?_queryFilter=true",
+          realm_path(realm)
+        ))
+        .header("authorization", format!("Bearer {}", token_str)),
+    )
+    .await?
+    .bytes()
+    .await?;
+
+  Ok(serde_json::from_slice(trees)?)
+}
+
 impl AuthenticationTreeList {
   pub fn get_tree_list(&self) -> Vec<String> {
     self.result.iter().map(|t| t.id.to_owned()).collect()
@@ -159,6 +184,17 @@ impl AuthenticationTreeList {
   pub fn get_tree(&self, name: &str) -> Option<Tree> {
     self.result.iter().find(|t| t.id.eq(name)).cloned()
   }
+  pub fn trees(&self) -> &[Tree] {
+    &self.result
+  }
+  /// Tree name to `_rev`, used to spot journeys edited since the last fetch.
+  pub fn revisions(&self) -> HashMap<&str, &str> {
+    self
+      .result
+      .iter()
+      .map(|t| (t.id.as_str(), t.rev.as_str()))
+      .collect()
+  }
 }
 
 #[derive(Serialize)]
@@ -170,8 +206,11 @@ enum EdgeType {
 }
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 struct ReactFlowEdgeStyle {
   stroke: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  stroke_width: Option<f32>,
 }
 
 #[derive(Serialize)]
@@ -184,6 +223,34 @@ pub struct ReactFlowEdge {
   target: String,
   source_handle: String,
   style: ReactFlowEdgeStyle,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  label: Option<String>,
+}
+
+/// Widest stroke drawn for the busiest edge of a heatmap.
+const MAX_HEAT_STROKE: f32 = 12.0;
+
+impl ReactFlowEdge {
+  pub fn source(&self) -> &str {
+    &self.source
+  }
+
+  pub fn source_handle(&self) -> &str {
+    &self.source_handle
+  }
+
+  /// Labels the edge with how often it was taken and scales its width against the busiest edge.
+  pub fn overlay_traffic(&mut self, count: usize, busiest: usize, failing: bool) {
+    self.label = Some(count.to_string());
+    self.style = ReactFlowEdgeStyle {
+      stroke: match (count, failing) {
+        (0, _) => "grey".to_string(),
+        (_, true) => "red".to_string(),
+        (_, false) => "green".to_string(),
+      },
+      stroke_width: Some(1.0 + (MAX_HEAT_STROKE - 1.0) * count as f32 / busiest.max(1) as f32),
+    };
+  }
 }
 
 #[derive(Serialize)]
@@ -192,6 +259,34 @@ struct Position {
   y: f32,
 }
 
+/// AM's built-in failure node, shared by every tree.
+pub const FAILURE_NODE_ID: &str = "e301438c-0bd0-429c-ab0c-66126501069a";
+
+#[derive(Clone, Copy, PartialEq)]
+enum VisitStatus {
+  NotVisited,
+  Visited,
+  Errored,
+  Terminal,
+}
+
+impl VisitStatus {
+  fn as_str(&self) -> &'static str {
+    match self {
+      VisitStatus::NotVisited => "not_visited",
+      VisitStatus::Visited => "visited",
+      VisitStatus::Errored => "errored",
+      VisitStatus::Terminal => "terminal",
+    }
+  }
+}
+
+/// Room left around an inner tree rendered inside its evaluator node.
+const SUBFLOW_PADDING: f32 = 40.0;
+/// Rough footprint of a single node, used to size subflow groups.
+const NODE_WIDTH: f32 = 200.0;
+const NODE_HEIGHT: f32 = 120.0;
+
 #[derive(Serialize, Default)]
 #[serde(rename_all = "lowercase")]
 enum HandlePosition {
@@ -230,6 +325,77 @@ pub struct ReactFlowNode {
   handles: Option<Vec<ReactFlowNodeHandle>>,
   source_position: HandlePosition,
   target_position: HandlePosition,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  parent_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  extent: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  width: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  height: Option<f32>,
+}
+
+impl ReactFlowNode {
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// Turns this node into the group that an expanded inner tree is drawn inside.
+  pub fn contain_subflow(&mut self, inner_tree: &str, (width, height): (f32, f32)) {
+    self.width = Some(width);
+    self.height = Some(height);
+    self
+      .data
+      .insert("innerTree".to_string(), inner_tree.to_string());
+  }
+}
+
+/// Re-homes a rendered inner tree inside the node `parent_id`.
+///
+/// Ids are prefixed with the parent so the same inner tree can appear more than once, and top level
+/// nodes are shifted to positions relative to the parent. Returns the size the parent needs to be.
+pub fn nest_subflow(
+  parent_id: &str,
+  nodes: &mut [ReactFlowNode],
+  edges: &mut [ReactFlowEdge],
+) -> (f32, f32) {
+  let prefixed = |id: &str| format!("{parent_id}:{id}");
+
+  let top_level = || nodes.iter().filter(|node| node.parent_id.is_none());
+  let min_x = top_level().map(|node| node.position.x).fold(f32::MAX, f32::min);
+  let min_y = top_level().map(|node| node.position.y).fold(f32::MAX, f32::min);
+  let max_x = top_level().map(|node| node.position.x).fold(f32::MIN, f32::max);
+  let max_y = top_level().map(|node| node.position.y).fold(f32::MIN, f32::max);
+
+  nodes.iter_mut().for_each(|node| {
+    node.id = prefixed(&node.id);
+    node.parent_id = Some(match &node.parent_id {
+      Some(grand_parent) => prefixed(grand_parent),
+      None => {
+        node.position = Position {
+          x: node.position.x - min_x + SUBFLOW_PADDING,
+          y: node.position.y - min_y + SUBFLOW_PADDING,
+        };
+        parent_id.to_string()
+      }
+    });
+    node.extent = Some("parent".to_string());
+  });
+
+  edges.iter_mut().for_each(|edge| {
+    edge.id = prefixed(&edge.id);
+    edge.source = prefixed(&edge.source);
+    edge.target = prefixed(&edge.target);
+  });
+
+  if nodes.is_empty() {
+    return (NODE_WIDTH, NODE_HEIGHT);
+  }
+
+  (
+    max_x - min_x + NODE_WIDTH + 2.0 * SUBFLOW_PADDING,
+    max_y - min_y + NODE_HEIGHT + 2.0 * SUBFLOW_PADDING,
+  )
 }
 
 impl Tree {
@@ -237,16 +403,93 @@ impl Tree {
     self.nodes.iter().map(|t| t.0).cloned().collect()
   }
 
+  /// Whether taking `outcome` from `node_id` fails the journey, either as an error or straight to the failure node.
+  pub fn is_failing_outcome(&self, node_id: &str, outcome: &str) -> bool {
+    outcome == "error"
+      || self
+        .nodes
+        .get(node_id)
+        .and_then(|node| node.connections.get(outcome))
+        .is_some_and(|target| target == FAILURE_NODE_ID)
+  }
+
+  /// Whether a logged outcome was produced by `node_id` in this tree.
+  ///
+  /// Logs carrying a node id are matched on (tree, node id); older logs without one fall back to
+  /// the display name, which is ambiguous when several nodes share it.
+  fn is_outcome_of(&self, outcome: &NodeOutcomeEdge, node_id: &str) -> bool {
+    if outcome
+      .tree_name
+      .as_ref()
+      .is_some_and(|tree_name| tree_name != &self.id)
+    {
+      return false;
+    }
+
+    match &outcome.node_id {
+      Some(outcome_node_id) => outcome_node_id == node_id,
+      None => self
+        .nodes
+        .get(node_id)
+        .is_some_and(|node| node.display_name == outcome.name),
+    }
+  }
+
+  /// Finds the node in this tree that logged an outcome, see [`Tree::is_outcome_of`].
+  pub fn resolve_node_id(
+    &self,
+    node_id: Option<&str>,
+    tree_name: Option<&str>,
+    display_name: &str,
+  ) -> Option<String> {
+    if tree_name.is_some_and(|tree_name| tree_name != self.id) {
+      return None;
+    }
+
+    match node_id {
+      Some(node_id) => self.nodes.contains_key(node_id).then(|| node_id.to_string()),
+      None => self
+        .nodes
+        .iter()
+        .find(|t| t.1.display_name == display_name)
+        .map(|t| t.0.clone()),
+    }
+  }
+
+  pub fn node_type(&self, node_id: &str) -> Option<String> {
+    self.nodes.get(node_id).map(|node| node.node_type.to_string())
+  }
+
+  pub fn inner_tree_node_ids(&self) -> Vec<String> {
+    self
+      .nodes
+      .iter()
+      .filter(|t| matches!(t.1.node_type, NodeType::InnerTreeEvaluatorNode))
+      .map(|t| t.0.clone())
+      .collect()
+  }
+
+  pub fn scripted_node_ids(&self) -> Vec<String> {
+    self
+      .nodes
+      .iter()
+      .filter(|t| matches!(t.1.node_type, NodeType::ScriptedDecisionNode))
+      .map(|t| t.0.clone())
+      .collect()
+  }
+
   pub async fn get_node_info(
     &self,
+    client: &LogClient,
     dom: &str,
     token_str: &str,
+    realm: &str,
   ) -> Result<HashMap<String, (NodeConfig, NodeData)>, ShowMeErrors> {
     let test = self
       .nodes
       .iter()
       .map(async |t| {
-        node_id_to_script_config(&t.1.node_type, t.0, dom, &token_str)
+        node_id_to_script_config(client, &t.1.node_type, t.0, dom, &token_str, realm)
           .await
           .map(|v| (t.0.clone(), v))
           .unwrap_or(("nothing".to_string(), (NodeConfig::None, NodeData::None)))
@@ -260,7 +503,7 @@ impl Tree {
     Ok(test)
   }
 
-  pub fn generate_edges(&self, outcomes: &Vec<NodeOutcomeEdge>) -> Vec<ReactFlowEdge> {
+  pub fn generate_edges(&self, outcomes: &[NodeOutcomeEdge]) -> Vec<ReactFlowEdge> {
     let start_edge = ReactFlowEdge {
       id: "startNode".to_string(),
       edge_type: EdgeType::Normal,
@@ -269,7 +512,9 @@ impl Tree {
       source_handle: "ok".to_string(),
       style: ReactFlowEdgeStyle {
         stroke: "green".to_string(),
+        stroke_width: None,
       },
+      label: None,
     };
 
     let mut rest = self
@@ -281,7 +526,7 @@ impl Tree {
 
           let node_outcome = outcomes
             .iter()
-            .find(|outcome| outcome.name == t.1.display_name && outcome.outcome == handle);
+            .find(|outcome| outcome.outcome == handle && self.is_outcome_of(outcome, t.0));
 
           ReactFlowEdge {
             id: format!("{}/{}", t.0.to_owned(), v.0.to_owned()),
@@ -304,7 +549,9 @@ impl Tree {
                 }
                 None => "grey".to_string(),
               },
+              stroke_width: None,
             },
+            label: None,
           }
         })
       })
@@ -313,11 +560,78 @@ impl Tree {
     rest.push(start_edge);
     rest
   }
-  pub fn generate_nodes(&self) -> Vec<ReactFlowNode> {
+  /// Describes how the transaction passed through a node: status, visit count, when it ran
+  /// and the outcome it last took, ready to merge into the node's `data`.
+  fn visit_data(&self, node_id: &str, outcomes: &[NodeOutcomeEdge]) -> Vec<(String, String)> {
+    let visits: Vec<&NodeOutcomeEdge> = outcomes
+      .iter()
+      .filter(|outcome| self.is_outcome_of(outcome, node_id))
+      .collect();
+
+    let reached_from = outcomes
+      .iter()
+      .filter_map(|outcome| {
+        self
+          .nodes
+          .iter()
+          .find(|t| self.is_outcome_of(outcome, t.0))
+          .and_then(|t| t.1.connections.get(&outcome.outcome))
+          .filter(|target| *target == node_id)
+          .map(|_| outcome.timestamp)
+      })
+      .collect::<Vec<_>>();
+
+    let status = if self.static_nodes.contains_key(node_id) {
+      if node_id == "startNode" && !outcomes.is_empty() {
+        VisitStatus::Visited
+      } else if !reached_from.is_empty() {
+        VisitStatus::Terminal
+      } else {
+        VisitStatus::NotVisited
+      }
+    } else if visits
+      .iter()
+      .any(|visit| self.is_failing_outcome(node_id, &visit.outcome))
+    {
+      VisitStatus::Errored
+    } else if visits.is_empty() {
+      VisitStatus::NotVisited
+    } else {
+      VisitStatus::Visited
+    };
+
+    // Terminal nodes never log an outcome, so they are timed by the edges that reached them.
+    let times: Vec<_> = if visits.is_empty() {
+      reached_from
+    } else {
+      visits.iter().map(|visit| visit.timestamp).collect()
+    };
+
+    let mut data = vec![
+      ("visitStatus".to_string(), status.as_str().to_string()),
+      ("visitCount".to_string(), times.len().to_string()),
+    ];
+    if let Some(first) = times.iter().min() {
+      data.push(("firstVisit".to_string(), first.to_rfc3339()));
+    }
+    if let Some(last) = times.iter().max() {
+      data.push(("lastVisit".to_string(), last.to_rfc3339()));
+    }
+    if let Some(last_visit) = visits.iter().max_by_key(|visit| visit.timestamp) {
+      data.push(("outcome".to_string(), last_visit.outcome.clone()));
+    }
+    data
+  }
+
+  pub fn generate_nodes(&self, outcomes: &[NodeOutcomeEdge]) -> Vec<ReactFlowNode> {
     let static_nodes = self.static_nodes.iter().map(|t| ReactFlowNode {
       id: t.0.to_owned(),
       position: Position { x: t.1.x, y: t.1.y },
-      data: HashMap::from([("name".to_string(), t.0.clone())]),
+      data: HashMap::from_iter(
+        [("name".to_string(), t.0.clone())]
+          .into_iter()
+          .chain(self.visit_data(t.0, outcomes)),
+      ),
       handles: Some(vec![ReactFlowNodeHandle {
         width: None,
         height: None,
@@ -329,6 +643,10 @@ impl Tree {
       }]),
       source_position: HandlePosition::Right,
       target_position: HandlePosition::Left,
+      parent_id: None,
+      extent: None,
+      width: None,
+      height: None,
     });
 
     let other_nodes = self.nodes.iter().map(|t| {
@@ -354,16 +672,24 @@ impl Tree {
           x: t.1.x.unwrap_or(0.0),
           y: t.1.y.unwrap_or(0.0),
         },
-        data: HashMap::from([
-          ("name".to_string(), t.1.display_name.clone()),
-          (
-            "type".to_string(),
-            serde_json::to_string(&t.1.node_type).unwrap_or("ToDo better".to_string()),
-          ),
-        ]),
+        data: HashMap::from_iter(
+          [
+            ("name".to_string(), t.1.display_name.clone()),
+            (
+              "type".to_string(),
+              serde_json::to_string(&t.1.node_type).unwrap_or("ToDo better".to_string()),
+            ),
+          ]
+          .into_iter()
+          .chain(self.visit_data(t.0, outcomes)),
+        ),
         handles: Some(test),
         source_position: HandlePosition::Right,
         target_position: HandlePosition::Left,
+        parent_id: None,
+        extent: None,
+        width: None,
+        height: None,
       }
     });
     static_nodes.chain(other_nodes).collect()
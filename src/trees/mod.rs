@@ -1,4 +1,9 @@
+pub mod history;
 pub mod journeys;
 pub mod nodes;
+pub mod refresh;
 
+pub mod replay;
+pub mod script_logs;
 pub mod service;
+pub mod stats;
@@ -1,6 +1,8 @@
 use crate::errors::ShowMeErrors;
+use crate::ping_logs::client::LogClient;
+use crate::realms::realm_path;
 use crate::trees::journeys::NodeType;
-use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
@@ -30,6 +32,15 @@ struct ScriptConfig {
   outcome_map: Vec<HashMap<String, String>>,
 }
 
+#[derive(Deserialize, Serialize, Clone)]
+pub struct InnerTreeConfig {
+  #[serde(rename = "_id")]
+  id: String,
+  #[serde(rename = "_rev")]
+  rev: String,
+  pub(crate) tree: String,
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 #[serde(tag = "type")]
 pub enum NodeConfig {
@@ -37,8 +48,18 @@ pub enum NodeConfig {
   None,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
-struct Script {
+impl NodeConfig {
+  /// The id of the script a scripted decision node runs.
+  pub fn script_id(&self) -> Option<&str> {
+    match self {
+      NodeConfig::ScriptConfig(config) => Some(&config.script),
+      NodeConfig::None => None,
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub(crate) struct Script {
   #[serde(rename = "_id")]
   id: String,
   name: String,
@@ -53,36 +74,117 @@ pub enum NodeData {
   None,
 }
 
+/// GETs an AM endpoint through the pooled client and parses the body.
+async fn fetch_am<T: DeserializeOwned>(
+  client: &LogClient,
+  url: String,
+  token_str: &str,
+) -> Result<T, ShowMeErrors> {
+  let response = client
+    .send(
+      client
+        .http()
+        .get(url)
+        .header("authorization", format!("Bearer {}", token_str)),
+    )
+    .await?;
+
+  Ok(serde_json::from_slice(&response.bytes().await?)?)
+}
+
 pub async fn node_id_to_script_config(
+  client: &LogClient,
   node_type: &NodeType,
   node_id: &str,
   dom: &str,
   token_str: &str,
+  realm: &str,
 ) -> Result<(NodeConfig, NodeData), ShowMeErrors> {
-  let client = Client::new();
-
-  let config_txt = &client.get(format!("{dom}/am/json/realms/root/realms/alpha/realm-config/authentication/authenticationtrees/nodes/{node_type}/{node_id}")).header("authorization", format!("Bearer {}", token_str)).send().await?.bytes()
-    .await.map_err(|e| {
-    dbg!(e);
-   ShowMeErrors::NoLogsFound("".to_string())
-  })?;
+  let realm_path = realm_path(realm);
 
-  let script_config: ScriptConfig = serde_json::from_slice(config_txt)?;
+  let script_config: ScriptConfig = fetch_am(
+    client,
+    format!("{dom}/am/json/{realm_path}/realm-config/authentication/authenticationtrees/nodes/{node_type}/{node_id}"),
+    token_str,
+  )
+  .await?;
 
   let script_id = &script_config.script;
 
-  let script_txt = &client
-    .get(format!("{dom}/am/json/alpha/scripts/{script_id}"))
-    .header("authorization", format!("Bearer {}", token_str))
-    .send()
-    .await?
-    .bytes()
-    .await?;
-
-  let script_data: Script = serde_json::from_slice(script_txt)?;
+  let script_data: Script = fetch_am(
+    client,
+    format!("{dom}/am/json/{realm_path}/scripts/{script_id}"),
+    token_str,
+  )
+  .await?;
 
   Ok((
     NodeConfig::ScriptConfig(script_config),
     NodeData::Scirpt(script_data),
   ))
 }
+
+/// A scripted decision node's config, without also fetching the script it points at.
+pub async fn scripted_node_config(
+  client: &LogClient,
+  node_id: &str,
+  dom: &str,
+  token_str: &str,
+  realm: &str,
+) -> Result<NodeConfig, ShowMeErrors> {
+  let config = fetch_am(
+    client,
+    format!("{dom}/am/json/{}/realm-config/authentication/authenticationtrees/nodes/{}/{node_id}", realm_path(realm), NodeType::ScriptedDecisionNode),
+    token_str,
+  )
+  .await?;
+
+  Ok(NodeConfig::ScriptConfig(config))
+}
+
+pub async fn node_id_to_inner_tree(
+  client: &LogClient,
+  node_id: &str,
+  dom: &str,
+  token_str: &str,
+  realm: &str,
+) -> Result<InnerTreeConfig, ShowMeErrors> {
+  fetch_am(
+    client,
+    format!("{dom}/am/json/{}/realm-config/authentication/authenticationtrees/nodes/{}/{node_id}", realm_path(realm), NodeType::InnerTreeEvaluatorNode),
+    token_str,
+  )
+  .await
+}
+
+/// A node's raw config as AM returns it, plus the body of the script it runs if it has one.
+pub async fn node_snapshot(
+  client: &LogClient,
+  node_type: &NodeType,
+  node_id: &str,
+  dom: &str,
+  token_str: &str,
+  realm: &str,
+) -> Result<(serde_json::Value, Option<Script>), ShowMeErrors> {
+  let realm_path = realm_path(realm);
+
+  let config: serde_json::Value = fetch_am(
+    client,
+    format!("{dom}/am/json/{realm_path}/realm-config/authentication/authenticationtrees/nodes/{node_type}/{node_id}"),
+    token_str,
+  )
+  .await?;
+
+  let Some(script_id) = config.get("script").and_then(|script| script.as_str()) else {
+    return Ok((config, None));
+  };
+
+  let script: Script = fetch_am(
+    client,
+    format!("{dom}/am/json/{realm_path}/scripts/{script_id}"),
+    token_str,
+  )
+  .await?;
+
+  Ok((config, Some(script)))
+}
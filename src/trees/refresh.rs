@@ -0,0 +1,265 @@
+use crate::AppMutState;
+use crate::errors::ShowMeErrors;
+use crate::ping_logs::client::LogClient;
+use crate::trees::history::capture_snapshot;
+use crate::trees::journeys::{AuthenticationTreeList, get_authentication_trees};
+use actix_web::rt::time::sleep;
+use actix_web::web;
+use actix_ws::Session;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+const TREE_REFRESH_SECS: u64 = 60;
+const MAX_TREE_CHANGES: usize = 256;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum TreeChangeKind {
+  Added { rev: String },
+  Removed { rev: String },
+  RevBumped { from: String, to: String },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct TreeChange {
+  seq: u64,
+  realm: String,
+  tree: String,
+  #[serde(flatten)]
+  kind: TreeChangeKind,
+  detected: DateTime<Utc>,
+}
+
+/// What changed between two fetches of a realm's tree list, sorted by tree name.
+fn diff_trees(
+  old: &AuthenticationTreeList,
+  new: &AuthenticationTreeList,
+) -> Vec<(String, TreeChangeKind)> {
+  let (old, new) = (old.revisions(), new.revisions());
+
+  let mut changes: Vec<(String, TreeChangeKind)> = new
+    .iter()
+    .filter_map(|(name, rev)| {
+      let kind = match old.get(name) {
+        None => TreeChangeKind::Added {
+          rev: rev.to_string(),
+        },
+        Some(from) if from != rev => TreeChangeKind::RevBumped {
+          from: from.to_string(),
+          to: rev.to_string(),
+        },
+        Some(_) => return None,
+      };
+      Some((name.to_string(), kind))
+    })
+    .chain(
+      old
+        .iter()
+        .filter(|(name, _)| !new.contains_key(*name))
+        .map(|(name, rev)| {
+          (
+            name.to_string(),
+            TreeChangeKind::Removed {
+              rev: rev.to_string(),
+            },
+          )
+        }),
+    )
+    .collect();
+
+  changes.sort_by(|a, b| a.0.cmp(&b.0));
+  changes
+}
+
+#[derive(Clone)]
+struct ChangeSubscriber {
+  id: u64,
+  realm: String,
+  session: Session,
+}
+
+/// Recent tree changes for polling clients plus the sockets they are pushed to as they happen.
+#[derive(Default)]
+pub(crate) struct TreeEvents {
+  next_seq: u64,
+  recent: VecDeque<TreeChange>,
+  next_id: u64,
+  subscribers: Vec<ChangeSubscriber>,
+}
+
+impl TreeEvents {
+  fn record(&mut self, realm: &str, changes: Vec<(String, TreeChangeKind)>) -> Vec<TreeChange> {
+    let detected = Utc::now();
+    let changes: Vec<TreeChange> = changes
+      .into_iter()
+      .map(|(tree, kind)| {
+        self.next_seq += 1;
+        TreeChange {
+          seq: self.next_seq,
+          realm: realm.to_string(),
+          tree,
+          kind,
+          detected,
+        }
+      })
+      .collect();
+
+    self.recent.extend(changes.iter().cloned());
+    while self.recent.len() > MAX_TREE_CHANGES {
+      self.recent.pop_front();
+    }
+    changes
+  }
+
+  /// Changes in `realm` with a sequence number above `seq`, oldest first.
+  pub(crate) fn since(&self, realm: &str, seq: u64) -> Vec<TreeChange> {
+    self
+      .recent
+      .iter()
+      .filter(|change| change.realm == realm && change.seq > seq)
+      .cloned()
+      .collect()
+  }
+
+  pub(crate) fn subscribe(&mut self, realm: &str, session: Session) -> u64 {
+    self.next_id += 1;
+    self.subscribers.push(ChangeSubscriber {
+      id: self.next_id,
+      realm: realm.to_string(),
+      session,
+    });
+    self.next_id
+  }
+
+  pub(crate) fn unsubscribe(&mut self, id: u64) {
+    self.subscribers.retain(|subscriber| subscriber.id != id);
+  }
+}
+
+/// Snapshots every tree in `trees` whose `_rev` the history hasn't seen yet.
+async fn record_revisions(
+  data: &AppMutState,
+  token_str: &str,
+  realm: &str,
+  trees: &AuthenticationTreeList,
+) -> Result<(), ShowMeErrors> {
+  let unseen: Vec<_> = {
+    let history = data
+      .tree_history
+      .lock()
+      .map_err(|_| ShowMeErrors::SharedLocking("tree history".into()))?;
+    trees
+      .trees()
+      .iter()
+      .filter(|tree| history.is_new_revision(realm, tree))
+      .collect()
+  };
+
+  // One tree at a time, each already fetching its nodes concurrently.
+  let mut snapshots = vec![];
+  for tree in unseen {
+    snapshots
+      .push(capture_snapshot(&data.log_client, tree, &data.token.dom, token_str, realm).await);
+  }
+
+  let mut history = data
+    .tree_history
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tree history".into()))?;
+  snapshots
+    .into_iter()
+    .try_for_each(|snapshot| history.record(snapshot))
+}
+
+/// Re-fetches one realm's trees, swaps them in and publishes whatever changed.
+pub(crate) async fn refresh_realm(
+  data: &AppMutState,
+  client: &LogClient,
+  realm: &str,
+) -> Result<Vec<TreeChange>, ShowMeErrors> {
+  let token_str = data.token.access_token().await?;
+  let fetched =
+    Arc::new(get_authentication_trees(client, &data.token.dom, &token_str, realm).await?);
+
+  if let Err(err) = record_revisions(data, &token_str, realm, &fetched).await {
+    println!(
+      "Could not record tree revisions for realm [{}]: {}",
+      realm, err
+    );
+  }
+
+  let previous = data
+    .authentication_tree
+    .write()
+    .map_err(|_| ShowMeErrors::SharedLocking("authentication trees".into()))?
+    .insert(realm.to_string(), fetched.clone());
+
+  let Some(previous) = previous else {
+    return Ok(vec![]);
+  };
+
+  let diff = diff_trees(&previous, &fetched);
+  if diff.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let (changes, subscribers) = {
+    let mut events = data
+      .tree_events
+      .lock()
+      .map_err(|_| ShowMeErrors::SharedLocking("tree events".into()))?;
+    let changes = events.record(realm, diff);
+    let subscribers: Vec<ChangeSubscriber> = events
+      .subscribers
+      .iter()
+      .filter(|subscriber| subscriber.realm == realm)
+      .cloned()
+      .collect();
+    (changes, subscribers)
+  };
+
+  let mut closed = vec![];
+  for mut subscriber in subscribers {
+    for change in &changes {
+      if subscriber
+        .session
+        .text(serde_json::to_string(change)?)
+        .await
+        .is_err()
+      {
+        closed.push(subscriber.id);
+        break;
+      }
+    }
+  }
+
+  let mut events = data
+    .tree_events
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tree events".into()))?;
+  closed.into_iter().for_each(|id| events.unsubscribe(id));
+
+  Ok(changes)
+}
+
+/// Keeps every configured realm's tree list in step with edits made in the admin console.
+pub(crate) async fn refresh_trees(data: web::Data<AppMutState>) -> Result<(), ShowMeErrors> {
+  let client = &data.log_client;
+
+  loop {
+    sleep(Duration::from_secs(TREE_REFRESH_SECS)).await;
+
+    for realm in &data.realms {
+      match refresh_realm(&data, client, realm).await {
+        Ok(changes) if !changes.is_empty() => {
+          println!("{} tree change(s) in realm [{}].", changes.len(), realm)
+        }
+        Ok(_) => {}
+        Err(err) => println!("Could not refresh trees for realm [{}]: {}", realm, err),
+      }
+    }
+  }
+}
@@ -0,0 +1,74 @@
+use crate::ping_logs::logs::ResultingLog;
+use crate::trees::journeys::Tree;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+
+#[derive(Serialize)]
+pub struct ReplayStep {
+  step: usize,
+  /// `None` for legacy logs written before AM recorded node ids.
+  node_id: Option<String>,
+  tree_name: Option<String>,
+  display_name: String,
+  /// `None` when the node belongs to an inner tree rather than the requested one.
+  node_type: Option<String>,
+  outcome: String,
+  timestamp: DateTime<Utc>,
+  /// Time since the previous step finished, `None` for the first step.
+  elapsed_ms: Option<i64>,
+  script_logs: Vec<ResultingLog>,
+}
+
+/// Orders a transaction's node outcomes into a step-by-step timeline.
+///
+/// Every visit is its own step, so retry loops and nodes sharing a display name stay distinct.
+/// Script output is attributed to the step whose outcome is the first logged after it.
+pub fn build_replay(
+  tree: &Tree,
+  mut outcome_logs: Vec<ResultingLog>,
+  mut script_logs: Vec<ResultingLog>,
+) -> Vec<ReplayStep> {
+  outcome_logs.sort_by_key(|log| log.timestamp);
+
+  let mut seen = HashSet::new();
+  script_logs.retain(|log| seen.insert(log.dedupe_key()));
+  script_logs.sort_by_key(|log| log.timestamp);
+  let mut script_logs = script_logs.into_iter().peekable();
+
+  let mut previous: Option<DateTime<Utc>> = None;
+  outcome_logs
+    .into_iter()
+    .filter_map(|log| {
+      Some((log.payload.node_outcome()?, log.timestamp?))
+    })
+    .enumerate()
+    .map(|(step, (info, timestamp))| {
+      let mut step_logs = vec![];
+      while let Some(script_log) = script_logs.next_if(|script_log| script_log.timestamp <= Some(timestamp)) {
+        step_logs.push(script_log);
+      }
+
+      let elapsed_ms = previous.map(|previous| (timestamp - previous).num_milliseconds());
+      previous = Some(timestamp);
+
+      ReplayStep {
+        step,
+        node_type: tree
+          .resolve_node_id(
+            info.node_id.as_deref(),
+            info.tree_name.as_deref(),
+            &info.display_name,
+          )
+          .and_then(|node_id| tree.node_type(&node_id)),
+        node_id: info.node_id,
+        tree_name: info.tree_name,
+        display_name: info.display_name,
+        outcome: info.node_outcome,
+        timestamp,
+        elapsed_ms,
+        script_logs: step_logs,
+      }
+    })
+    .collect()
+}
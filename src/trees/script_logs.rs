@@ -0,0 +1,130 @@
+use crate::ping_logs::logs::ResultingLog;
+use crate::trees::journeys::Tree;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Scripts log under `scripts.<context>.<script id>`, e.g. `scripts.AUTHENTICATION_TREE_DECISION_NODE.<id>`.
+const SCRIPT_LOGGER_PREFIX: &str = "scripts.";
+
+#[derive(Serialize)]
+pub struct ScriptVisit {
+  outcome: String,
+  /// When the previous node finished, `None` for the first node of the attempt.
+  started: Option<DateTime<Utc>>,
+  finished: DateTime<Utc>,
+  logs: Vec<ResultingLog>,
+}
+
+#[derive(Serialize)]
+pub struct NodeScriptLogs {
+  display_name: String,
+  /// `None` when the node's config couldn't be read.
+  script_id: Option<String>,
+  visits: Vec<ScriptVisit>,
+}
+
+#[derive(Serialize)]
+pub struct ScriptLogBundles {
+  /// Every scripted node in the tree keyed by node id, including ones that logged nothing.
+  nodes: HashMap<String, NodeScriptLogs>,
+  /// Script output that no visit in this tree accounts for, e.g. from an inner tree's scripts.
+  unattributed: Vec<ResultingLog>,
+}
+
+/// The script id at the end of a script logger name, if it has one.
+fn logger_script_id(logger: &str) -> Option<&str> {
+  logger
+    .strip_prefix(SCRIPT_LOGGER_PREFIX)?
+    .rsplit_once('.')
+    .map(|(_, script_id)| script_id)
+}
+
+/// Splits a login attempt's logs into script output per scripted node visit.
+///
+/// A visit owns the script lines logged after the previous node outcome and up to its own, as long
+/// as their logger names the script the node runs; `script_ids` maps node ids to those scripts.
+pub fn correlate_script_logs(
+  tree: &Tree,
+  script_ids: &HashMap<String, String>,
+  mut logs: Vec<ResultingLog>,
+) -> ScriptLogBundles {
+  let mut seen = HashSet::new();
+  logs.retain(|log| seen.insert(log.dedupe_key()));
+  logs.sort_by_key(|log| log.timestamp);
+
+  let (outcome_logs, other_logs): (Vec<_>, Vec<_>) = logs
+    .into_iter()
+    .partition(|log| log.payload.node_outcome().is_some());
+  // Lines without a time can't be placed between node outcomes.
+  let (script_lines, mut unattributed): (Vec<_>, Vec<_>) = other_logs
+    .into_iter()
+    .filter(|log| {
+      log
+        .payload
+        .logger()
+        .is_some_and(|logger| logger.starts_with(SCRIPT_LOGGER_PREFIX))
+    })
+    .partition(|log| log.timestamp.is_some());
+  let mut script_lines = script_lines.into_iter().peekable();
+
+  let mut nodes: HashMap<String, NodeScriptLogs> = tree
+    .scripted_node_ids()
+    .into_iter()
+    .map(|node_id| {
+      let bundle = NodeScriptLogs {
+        display_name: tree.nodes[&node_id].display_name.clone(),
+        script_id: script_ids.get(&node_id).cloned(),
+        visits: vec![],
+      };
+      (node_id, bundle)
+    })
+    .collect();
+
+  let mut previous: Option<DateTime<Utc>> = None;
+  for log in outcome_logs {
+    let (Some(info), Some(finished)) = (log.payload.node_outcome(), log.timestamp) else {
+      continue;
+    };
+
+    let mut lines = vec![];
+    while let Some(line) = script_lines.next_if(|line| line.timestamp <= Some(finished)) {
+      lines.push(line);
+    }
+
+    let started = previous.replace(finished);
+    let node = tree
+      .resolve_node_id(
+        info.node_id.as_deref(),
+        info.tree_name.as_deref(),
+        &info.display_name,
+      )
+      .and_then(|node_id| nodes.get_mut(&node_id));
+
+    let Some(node) = node else {
+      unattributed.append(&mut lines);
+      continue;
+    };
+
+    let (logs, others): (Vec<_>, Vec<_>) = lines.into_iter().partition(|line| {
+      match (line.payload.logger().and_then(logger_script_id), &node.script_id) {
+        (Some(logged), Some(expected)) => logged == expected,
+        _ => true,
+      }
+    });
+    unattributed.extend(others);
+
+    node.visits.push(ScriptVisit {
+      outcome: info.node_outcome,
+      started,
+      finished,
+      logs,
+    });
+  }
+  unattributed.extend(script_lines);
+
+  ScriptLogBundles {
+    nodes,
+    unattributed,
+  }
+}
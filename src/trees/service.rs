@@ -1,47 +1,179 @@
 use crate::errors::ShowMeErrors;
-use crate::ping_logs::logs::{ResultingLog, get_logs};
-use crate::token::get_usable_token;
-use crate::trees::journeys::{ReactFlowEdge, ReactFlowNode};
-use crate::trees::nodes::{NodeConfig, NodeData};
+use crate::ping_logs::client::{LogClient, LogQuery};
+use crate::ping_logs::logs::{CollectedLogs, LogPaging, ResultingLog, collect_logs, stream_logs};
+use crate::ping_logs::query_filter::QueryFilter;
+use crate::ping_logs::tracking_cache::{TrackingCache, TrackingCacheStats};
+use crate::ping_logs::window::TimeWindow;
+use crate::realms::Realm;
+use crate::trees::history::{RevisionSummary, TreeDiff};
+use crate::trees::journeys::{
+  AuthenticationTreeList, ReactFlowEdge, ReactFlowNode, Tree, nest_subflow,
+};
+use crate::trees::nodes::{NodeConfig, NodeData, node_id_to_inner_tree, scripted_node_config};
+use crate::trees::refresh::{TreeChange, refresh_realm};
+use crate::trees::replay::{ReplayStep, build_replay};
+use crate::trees::script_logs::{ScriptLogBundles, correlate_script_logs};
+use crate::trees::stats::{JourneyStats, aggregate, overlay_traffic};
 use crate::workers::scripts::{RichScript, ScriptConfig};
 use crate::{AppMutState, NodeOutcomeEdge};
 use actix_web::web::Query;
-use actix_web::{get, web};
+use actix_web::{HttpRequest, HttpResponse, get, post, rt, web};
+use actix_ws::Message;
 use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use futures::future::LocalBoxFuture;
 use futures_util::future;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
 use std::ops::Deref;
 
+/// `{name}` segment of the journey routes; a struct so the `{realm}` prefix can sit alongside it.
+#[derive(Deserialize)]
+struct JourneyPath {
+  name: String,
+}
+
 #[derive(Deserialize)]
 struct JourneyFlowQuery {
   transaction_id: Option<String>,
+  max_pages: Option<usize>,
+  max_records: Option<usize>,
+  begin_time: Option<String>,
+  end_time: Option<String>,
+  expand_inner: Option<bool>,
+  depth: Option<usize>,
+}
+
+/// Where to read inner tree node configs from while expanding a flow.
+struct ConfigSource<'a> {
+  client: &'a LogClient,
+  dom: &'a str,
+  token_str: &'a str,
+  realm: &'a str,
+}
+
+const DEFAULT_INNER_DEPTH: usize = 1;
+const MAX_INNER_DEPTH: usize = 5;
+
+/// Renders a tree and, while `depth` allows, draws each inner tree inside its evaluator node.
+///
+/// `ancestors` holds the trees already being expanded on this branch so a journey that
+/// (indirectly) evaluates itself is drawn as a plain node instead of recursing forever.
+fn expand_flow<'a>(
+  tree: &'a Tree,
+  trees: &'a AuthenticationTreeList,
+  outcomes: &'a [NodeOutcomeEdge],
+  source: &'a ConfigSource<'a>,
+  depth: usize,
+  ancestors: Vec<String>,
+) -> LocalBoxFuture<'a, Result<FlowPayload, ShowMeErrors>> {
+  Box::pin(async move {
+    let mut nodes = tree.generate_nodes(outcomes);
+    let mut edges = tree.generate_edges(outcomes);
+
+    if depth == 0 {
+      return Ok(FlowPayload { nodes, edges });
+    }
+
+    for node_id in tree.inner_tree_node_ids() {
+      let inner_name = match node_id_to_inner_tree(source.client, &node_id, source.dom, source.token_str, source.realm).await {
+        Ok(config) => config.tree,
+        Err(err) => {
+          println!("Could not read inner tree config for [{}]: {}", node_id, err);
+          continue;
+        }
+      };
+
+      if ancestors.contains(&inner_name) {
+        println!("Not expanding [{}] again, it is already an ancestor.", inner_name);
+        continue;
+      }
+      let Some(inner_tree) = trees.get_tree(&inner_name) else {
+        continue;
+      };
+
+      let mut inner_ancestors = ancestors.clone();
+      inner_ancestors.push(inner_name.clone());
+      let mut inner = expand_flow(
+        &inner_tree,
+        trees,
+        outcomes,
+        source,
+        depth - 1,
+        inner_ancestors,
+      )
+      .await?;
+
+      let size = nest_subflow(&node_id, &mut inner.nodes, &mut inner.edges);
+      if let Some(parent) = nodes.iter_mut().find(|node| node.id() == node_id) {
+        parent.contain_subflow(&inner_name, size);
+      }
+
+      nodes.append(&mut inner.nodes);
+      edges.append(&mut inner.edges);
+    }
+
+    Ok(FlowPayload { nodes, edges })
+  })
 }
 
 #[get("/{name}/flow")]
 async fn journey_flow(
-  name: web::Path<String>,
+  path: web::Path<JourneyPath>,
+  realm: Realm,
   query: Query<JourneyFlowQuery>,
   data: web::Data<AppMutState>,
 ) -> Result<web::Json<FlowPayload>, ShowMeErrors> {
   let transaction_id = &query.transaction_id;
-  let tree = data.authentication_tree.get_tree(&name);
+  let trees = data.realm_trees(&realm)?;
+  let tree_jouney = trees
+    .get_tree(&path.name)
+    .ok_or_else(|| ShowMeErrors::NoJourneyFound(path.name.clone()))?;
+  let window = TimeWindow::parse(query.begin_time.as_deref(), query.end_time.as_deref())?;
 
-  let node_outcomes = (match transaction_id {
-    Some(id) => get_node_outcomes(id).await,
-    None => Ok(vec![]),
-  })
-  .unwrap_or(vec![]);
-
-  match tree {
-    None => Err(ShowMeErrors::NoLogsFound(
-      "ToDo: Make a real error".to_string(),
-    )),
-    Some(tree_jouney) => Ok(web::Json(FlowPayload {
-      nodes: tree_jouney.generate_nodes(),
+  let node_outcomes = match transaction_id {
+    Some(id) => {
+      get_node_outcomes(
+        &data.tracking_cache,
+        &data.log_client,
+        id,
+        window,
+        LogPaging::new(query.max_pages, query.max_records),
+      )
+      .await?
+    }
+    None => vec![],
+  };
+
+  if query.expand_inner.unwrap_or(false) {
+    let token_str = data.token.access_token().await?;
+    let depth = query
+      .depth
+      .unwrap_or(DEFAULT_INNER_DEPTH)
+      .min(MAX_INNER_DEPTH);
+
+    Ok(web::Json(
+      expand_flow(
+        &tree_jouney,
+        &trees,
+        &node_outcomes,
+        &ConfigSource {
+          client: &data.log_client,
+          dom: &data.token.dom,
+          token_str: &token_str,
+          realm: &realm.0,
+        },
+        depth,
+        vec![path.into_inner().name],
+      )
+      .await?,
+    ))
+  } else {
+    Ok(web::Json(FlowPayload {
+      nodes: tree_jouney.generate_nodes(&node_outcomes),
       edges: tree_jouney.generate_edges(&node_outcomes),
-    })),
+    }))
   }
 }
 
@@ -53,61 +185,131 @@ struct JourneyTransaction {
 
 #[get("/scripts")]
 async fn list_scripts(
+  realm: Realm,
   data: web::Data<AppMutState>,
 ) -> Result<web::Json<HashMap<String, ScriptConfig>>, ShowMeErrors> {
-  let saved_scripts = (*data
+  let saved_scripts = data
     .script_config
     .lock()
-    .map_err(|_| ShowMeErrors::SharedLocking("get scripts endpoint".to_string()))?)
-  .clone();
+    .map_err(|_| ShowMeErrors::SharedLocking("get scripts endpoint".to_string()))?
+    .get(&realm.0)
+    .cloned()
+    .unwrap_or_default();
 
   Ok(web::Json(saved_scripts))
 }
 
 
+/// Every node outcome logged for a tree in `realm`, across all transactions.
+fn journey_outcomes_filter(journey_name: &str, Realm(realm): &Realm) -> QueryFilter {
+  QueryFilter::eq("/payload/entries/info/treeName", journey_name)
+    .and(QueryFilter::eq("/payload/realm", format!("/{realm}")))
+    .and(QueryFilter::pr("/payload/entries/info/nodeOutcome"))
+    .and(QueryFilter::eq("/payload/eventName", "AM-NODE-LOGIN-COMPLETED"))
+}
+
+#[derive(Deserialize)]
+struct JourneyTransactionsQuery {
+  max_pages: Option<usize>,
+  max_records: Option<usize>,
+  begin_time: Option<String>,
+  end_time: Option<String>,
+}
+
 #[get("/{name}/transactions")]
 async fn get_journey_transactions(
-  journey_name: web::Path<String>,
+  path: web::Path<JourneyPath>,
+  realm: Realm,
+  query: Query<JourneyTransactionsQuery>,
+  data: web::Data<AppMutState>,
 ) -> Result<web::Json<Vec<JourneyTransaction>>, ShowMeErrors> {
-  let journey_transactions = get_logs(
-    &Client::new(),
-    "", // Blank transaction ID effectively runs a * search.
-    Some(
-      format!(
-        "/payload/entries/info/treeName eq \"{}\" and /payload/entries/info/nodeOutcome pr and /payload/eventName eq \"AM-NODE-LOGIN-COMPLETED\"",
-        journey_name
-      )
-        .as_str(),
-    ),
+  let window = TimeWindow::parse(query.begin_time.as_deref(), query.end_time.as_deref())?;
+  let log_query = LogQuery::all()
+    .filter(journey_outcomes_filter(&path.name, &realm))
+    .window(window);
+
+  let mut journey_transactions: Vec<JourneyTransaction> = stream_logs(
+    &data.log_client,
+    log_query,
+    LogPaging::new(query.max_pages, query.max_records),
   )
-    .await;
-
-  match journey_transactions {
-    Ok(transactions) => Ok(web::Json(
-      transactions
-        .result
-        .iter()
-        .map(|log| JourneyTransaction {
-          timestamp: log.timestamp,
-          transaction_id: log.payload.transaction_id.clone(),
-        })
-        .collect(),
-    )),
-    Err(err) => Err(err),
-  }
+  // An outcome without a transaction id or time can't be listed or opened, so it is left out.
+  .try_filter_map(|log| {
+    future::ready(Ok(
+      log
+        .timestamp
+        .zip(log.payload.transaction_id())
+        .map(|(timestamp, transaction_id)| JourneyTransaction {
+          timestamp,
+          transaction_id: transaction_id.to_string(),
+        }),
+    ))
+  })
+  .try_collect()
+  .await?;
+
+  journey_transactions.sort_by_key(|transaction| transaction.timestamp);
+
+  Ok(web::Json(journey_transactions))
+}
+
+#[derive(Serialize)]
+struct JourneyStatsPayload {
+  stats: JourneyStats,
+  flow: FlowPayload,
+}
+
+#[get("/{name}/stats")]
+async fn get_journey_stats(
+  path: web::Path<JourneyPath>,
+  realm: Realm,
+  query: Query<JourneyTransactionsQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<JourneyStatsPayload>, ShowMeErrors> {
+  let tree = data
+    .realm_trees(&realm)?
+    .get_tree(&path.name)
+    .ok_or_else(|| ShowMeErrors::NoJourneyFound(path.name.clone()))?;
+
+  let window = TimeWindow::parse(query.begin_time.as_deref(), query.end_time.as_deref())?;
+  let log_query = LogQuery::all()
+    .filter(journey_outcomes_filter(&path.name, &realm))
+    .window(window);
+
+  let outcome_logs = stream_logs(
+    &data.log_client,
+    log_query,
+    LogPaging::new(query.max_pages, query.max_records),
+  )
+  .try_collect()
+  .await?;
+
+  let (stats, traffic) = aggregate(&tree, outcome_logs);
+
+  let mut edges = tree.generate_edges(&[]);
+  overlay_traffic(&tree, &mut edges, &traffic);
+
+  Ok(web::Json(JourneyStatsPayload {
+    stats,
+    flow: FlowPayload {
+      nodes: tree.generate_nodes(&[]),
+      edges,
+    },
+  }))
 }
 
 #[get("/{name}/scripts")]
 async fn journey_script(
-  name: web::Path<String>,
+  path: web::Path<JourneyPath>,
+  realm: Realm,
   data: web::Data<AppMutState>,
 ) -> Result<web::Json<HashMap<String, (NodeConfig, NodeData)>>, ShowMeErrors> {
-  let (token_str, payload) = get_usable_token(&data.token, &data.payload, &data.token_str).await?;
+  let token_str = data.token.access_token().await?;
 
   let dom = &data.token.dom;
-  let tree = match data.authentication_tree.get_tree(&name.into_inner()) {
+  let tree = match data.realm_trees(&realm)?.get_tree(&path.name) {
     None => HashMap::new(),
-    Some(data) => data.get_node_info(dom, &token_str).await?,
+    Some(tree) => tree.get_node_info(&data.log_client, dom, &token_str, &realm.0).await?,
   };
 
   Ok(web::Json(tree))
@@ -128,6 +330,7 @@ struct JourneyFilter {
 
 #[get("")]
 async fn get_journey(
+  realm: Realm,
   data: web::Data<AppMutState>,
   query: Query<JourneyFilter>,
 ) -> Result<web::Json<Vec<String>>, ShowMeErrors> {
@@ -136,7 +339,7 @@ async fn get_journey(
   let cont = query.contains.clone().unwrap_or_default();
 
   let tree_list = data
-    .authentication_tree
+    .realm_trees(&realm)?
     .get_tree_list()
     .iter()
     .filter(|t| {
@@ -166,82 +369,385 @@ async fn get_journey(
   Ok(web::Json(tree_list))
 }
 
-async fn get_node_outcomes(transaction_id: &str) -> Result<Vec<NodeOutcomeEdge>, ShowMeErrors> {
-  let client = &Client::new();
+fn lock_tracking_cache(
+  cache: &Mutex<TrackingCache>,
+) -> Result<MutexGuard<'_, TrackingCache>, ShowMeErrors> {
+  cache
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tracking cache".into()))
+}
+
+/// The tracking ids that tie every request of a login attempt to `transaction_id`.
+async fn linked_tracking_ids(
+  cache: &Mutex<TrackingCache>,
+  client: &LogClient,
+  transaction_id: &str,
+  window: TimeWindow,
+  paging: LogPaging,
+) -> Result<Vec<String>, ShowMeErrors> {
+  if let Some(tracking_ids) = lock_tracking_cache(cache)?.tracking_ids(transaction_id, &window) {
+    return Ok(tracking_ids);
+  }
 
   // Get latest node outcomes with tracking IDs
-  let most_recent_transaction_logs = get_logs(
-    client,
-    &transaction_id,
-    Some("/payload/entries/info/nodeOutcome pr"),
-  )
-  .await
-  .map_or(vec![], |some_logs| some_logs.result);
+  let log_query = LogQuery::transaction(transaction_id)
+    .filter(QueryFilter::pr("/payload/entries/info/nodeOutcome"))
+    .window(window);
 
-  let mut tracking_ids: Vec<String> = most_recent_transaction_logs
-    .clone()
+  let collected = collect_logs(client, log_query, paging).await?;
+  let mut tracking_ids: Vec<String> = collected
+    .logs
     .iter()
-    .flat_map(|log| log.payload.tracking_ids.clone())
+    .flat_map(|log| log.payload.tracking_ids().to_vec())
     .collect::<HashSet<_>>()
     .into_iter()
     .collect();
 
   tracking_ids.sort();
 
-  let async_logs = future::join_all(if !tracking_ids.is_empty() {
+  // A capped search may have missed some, so only a complete one is worth remembering.
+  if !tracking_ids.is_empty() && !collected.truncated {
+    lock_tracking_cache(cache)?.link(transaction_id, window, tracking_ids.clone());
+  }
+  Ok(tracking_ids)
+}
+
+/// Every node outcome log for the login attempt behind `transaction_id`, oldest first.
+async fn get_outcome_logs(
+  cache: &Mutex<TrackingCache>,
+  client: &LogClient,
+  transaction_id: &str,
+  window: TimeWindow,
+  paging: LogPaging,
+) -> Result<Vec<ResultingLog>, ShowMeErrors> {
+  let tracking_ids = linked_tracking_ids(cache, client, transaction_id, window, paging).await?;
+
+  let async_logs = future::join_all(
     tracking_ids
       .into_iter() // takes ownership of each String
       .map(|tracking_id| async move {
-        println!(
-          "Getting logs for additional tracking ID [{:?}]...",
-          tracking_id
-        );
+        let windows = lock_tracking_cache(cache)?
+          .plan_fetch(&tracking_id, &window)
+          .windows(window);
 
-        let some_logs = get_logs(
-          client,
-          "",
-          Some(&format!(
-            "/payload/trackingIds eq \"{}\" and /payload/entries/info/nodeOutcome pr",
+        let fetched = if windows.is_empty() {
+          None
+        } else {
+          println!(
+            "Getting logs for additional tracking ID [{:?}]...",
             tracking_id
-          )),
-        )
-        .await;
+          );
+
+          let query_filter = QueryFilter::eq("/payload/trackingIds", &tracking_id)
+            .and(QueryFilter::pr("/payload/entries/info/nodeOutcome"));
+
+          future::try_join_all(windows.iter().map(|gap| {
+            collect_logs(client, LogQuery::all().filter(query_filter.clone()).window(*gap), paging)
+          }))
+          .await
+          .map(|parts| CollectedLogs {
+            truncated: parts.iter().any(|part| part.truncated),
+            logs: parts.into_iter().flat_map(|part| part.logs).collect(),
+          })
+          .inspect_err(|err| println!("{}", err))
+          .ok()
+        };
 
-        let final_logs = some_logs.map_or(vec![], |some_logs| some_logs.result);
+        let all_logs = lock_tracking_cache(cache)?.record(&tracking_id, &windows, fetched);
 
         println!(
           "Got  [{:?}] logs for tracking ID [{:?}].",
-          final_logs.len(),
+          all_logs.len(),
           tracking_id,
         );
 
-        final_logs
-      })
-      .collect()
-  } else {
-    vec![]
-  })
+        Ok::<_, ShowMeErrors>(all_logs)
+      }),
+  )
   .await;
 
-  // Perform query for all other node outcomes in the journey with the tracking ID
-  let all_transaction_logs: Vec<&ResultingLog> =
-    async_logs.iter().flat_map(|results| results).collect();
+  // A record carrying several tracking ids comes back once per id.
+  let mut seen = HashSet::new();
+  let mut outcome_logs: Vec<ResultingLog> = async_logs
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?
+    .into_iter()
+    .flatten()
+    .filter(|log| {
+      log.timestamp.is_some_and(|timestamp| window.contains(timestamp))
+        && seen.insert(log.dedupe_key())
+    })
+    .collect();
 
+  outcome_logs.sort_by_key(|log| log.timestamp);
+  Ok(outcome_logs)
+}
+
+async fn get_node_outcomes(
+  cache: &Mutex<TrackingCache>,
+  client: &LogClient,
+  transaction_id: &str,
+  window: TimeWindow,
+  paging: LogPaging,
+) -> Result<Vec<NodeOutcomeEdge>, ShowMeErrors> {
+  // Perform query for all other node outcomes in the journey with the tracking ID
   Ok(
-    all_transaction_logs
-      .iter()
-      .map(|log| {
-        let thing = &log.payload.entries.clone().unwrap_or(vec![])[0];
-
-        NodeOutcomeEdge {
-          name: thing.info.display_name.clone(),
-          outcome: thing.info.node_outcome.clone(),
-        }
+    get_outcome_logs(cache, client, transaction_id, window, paging)
+      .await?
+      .into_iter()
+      .filter_map(|log| {
+        let timestamp = log.timestamp?;
+        log.payload.node_outcome().map(|info| NodeOutcomeEdge {
+          name: info.display_name,
+          outcome: info.node_outcome,
+          timestamp,
+          node_id: info.node_id,
+          tree_name: info.tree_name,
+        })
       })
       .collect(),
   )
 }
 
+#[derive(Deserialize)]
+struct JourneyReplayQuery {
+  transaction_id: String,
+  max_pages: Option<usize>,
+  max_records: Option<usize>,
+  begin_time: Option<String>,
+  end_time: Option<String>,
+}
+
+#[get("/{name}/replay")]
+async fn journey_replay(
+  path: web::Path<JourneyPath>,
+  realm: Realm,
+  query: Query<JourneyReplayQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Vec<ReplayStep>>, ShowMeErrors> {
+  let tree = data
+    .realm_trees(&realm)?
+    .get_tree(&path.name)
+    .ok_or_else(|| ShowMeErrors::NoJourneyFound(path.name.clone()))?;
+
+  let window = TimeWindow::parse(query.begin_time.as_deref(), query.end_time.as_deref())?;
+  let paging = LogPaging::new(query.max_pages, query.max_records);
+  let client = &data.log_client;
+
+  let outcome_logs = get_outcome_logs(
+    &data.tracking_cache,
+    client,
+    &query.transaction_id,
+    window,
+    paging,
+  )
+  .await?;
+  let tracking_ids = linked_tracking_ids(
+    &data.tracking_cache,
+    client,
+    &query.transaction_id,
+    window,
+    paging,
+  )
+  .await?;
+
+  let script_logs = future::join_all(tracking_ids.iter().map(|tracking_id| async move {
+    let query_filter = QueryFilter::eq("/payload/trackingIds", tracking_id)
+      .and(QueryFilter::sw("/payload/logger", "scripts."));
+
+    stream_logs(client, LogQuery::all().filter(query_filter).window(window), paging)
+      .try_collect::<Vec<_>>()
+      .await
+  }))
+  .await
+  .into_iter()
+  .collect::<Result<Vec<_>, _>>()?
+  .into_iter()
+  .flatten()
+  .collect();
+
+  Ok(web::Json(build_replay(&tree, outcome_logs, script_logs)))
+}
+
+/// Script output for each scripted node of the journey during one login attempt.
+///
+/// The per-script logs API is unreliable, so the attempt's logs are fetched once and attributed to
+/// nodes by logger name, the script each node runs and the gaps between node outcomes.
+#[get("/{name}/script-logs")]
+async fn journey_script_logs(
+  path: web::Path<JourneyPath>,
+  realm: Realm,
+  query: Query<JourneyReplayQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<ScriptLogBundles>, ShowMeErrors> {
+  let tree = data
+    .realm_trees(&realm)?
+    .get_tree(&path.name)
+    .ok_or_else(|| ShowMeErrors::NoJourneyFound(path.name.clone()))?;
+
+  let window = TimeWindow::parse(query.begin_time.as_deref(), query.end_time.as_deref())?;
+  let paging = LogPaging::new(query.max_pages, query.max_records);
+  let client = &data.log_client;
+
+  let tracking_ids = linked_tracking_ids(
+    &data.tracking_cache,
+    client,
+    &query.transaction_id,
+    window,
+    paging,
+  )
+  .await?;
+  let attempt_filter = tracking_ids
+    .iter()
+    .map(|tracking_id| QueryFilter::eq("/payload/trackingIds", tracking_id))
+    .fold(
+      QueryFilter::sw("/payload/transactionId", &query.transaction_id),
+      QueryFilter::or,
+    );
+
+  let logs: Vec<ResultingLog> =
+    stream_logs(client, LogQuery::all().filter(attempt_filter).window(window), paging)
+      .try_collect()
+      .await?;
+
+  let token_str = data.token.access_token().await?;
+  let (dom, token_str, realm_name) = (&data.token.dom, &token_str, &realm.0);
+  let script_ids: HashMap<String, String> =
+    future::join_all(tree.scripted_node_ids().into_iter().map(|node_id| async move {
+      let config = scripted_node_config(client, &node_id, dom, token_str, realm_name).await;
+      (node_id, config)
+    }))
+    .await
+    .into_iter()
+    .filter_map(|(node_id, config)| match config {
+      Ok(config) => config.script_id().map(|script_id| (node_id, script_id.to_string())),
+      Err(err) => {
+        println!("Could not read script config for [{}]: {}", node_id, err);
+        None
+      }
+    })
+    .collect();
+
+  Ok(web::Json(correlate_script_logs(&tree, &script_ids, logs)))
+}
+
+#[get("/{name}/revisions")]
+async fn journey_revisions(
+  path: web::Path<JourneyPath>,
+  realm: Realm,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Vec<RevisionSummary>>, ShowMeErrors> {
+  let revisions = data
+    .tree_history
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tree history".into()))?
+    .revisions(&realm.0, &path.name);
+
+  Ok(web::Json(revisions))
+}
+
+#[derive(Deserialize)]
+struct JourneyDiffQuery {
+  from: Option<String>,
+  to: Option<String>,
+}
+
+#[get("/{name}/diff")]
+async fn journey_diff(
+  path: web::Path<JourneyPath>,
+  realm: Realm,
+  query: Query<JourneyDiffQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<TreeDiff>, ShowMeErrors> {
+  let diff = data
+    .tree_history
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tree history".into()))?
+    .diff(&realm.0, &path.name, query.from.as_deref(), query.to.as_deref())?;
+
+  Ok(web::Json(diff))
+}
+
+/// Re-reads the realm's trees now instead of waiting for the background refresh.
+#[post("/refresh")]
+async fn refresh_journeys(
+  realm: Realm,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Vec<TreeChange>>, ShowMeErrors> {
+  Ok(web::Json(refresh_realm(&data, &data.log_client, &realm.0).await?))
+}
+
+#[derive(Deserialize)]
+struct JourneyChangesQuery {
+  since: Option<u64>,
+}
+
+#[get("/changes")]
+async fn journey_changes(
+  realm: Realm,
+  query: Query<JourneyChangesQuery>,
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<Vec<TreeChange>>, ShowMeErrors> {
+  let changes = data
+    .tree_events
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tree events".into()))?
+    .since(&realm.0, query.since.unwrap_or_default());
+
+  Ok(web::Json(changes))
+}
+
+/// Pushes each tree added, removed or re-revisioned in the realm as it is detected.
+#[get("/changes/ws")]
+async fn journey_changes_ws(
+  realm: Realm,
+  req: HttpRequest,
+  body: web::Payload,
+  data: web::Data<AppMutState>,
+) -> Result<HttpResponse, ShowMeErrors> {
+  let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+
+  let subscriber_id = data
+    .tree_events
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tree events".into()))?
+    .subscribe(&realm.0, session.clone());
+
+  rt::spawn(async move {
+    while let Some(Ok(message)) = messages.next().await {
+      let keep_open = match message {
+        Message::Ping(bytes) => session.pong(&bytes).await.is_ok(),
+        Message::Close(_) => false,
+        _ => true,
+      };
+
+      if !keep_open {
+        break;
+      }
+    }
+
+    if let Ok(mut events) = data.tree_events.lock() {
+      events.unsubscribe(subscriber_id);
+    }
+    let _ = session.close(None).await;
+  });
+
+  Ok(response)
+}
+
+#[get("/outcome-cache")]
+async fn outcome_cache_stats(
+  data: web::Data<AppMutState>,
+) -> Result<web::Json<TrackingCacheStats>, ShowMeErrors> {
+  let stats = data
+    .tracking_cache
+    .lock()
+    .map_err(|_| ShowMeErrors::SharedLocking("tracking cache".into()))?
+    .stats();
+
+  Ok(web::Json(stats))
+}
+
 // this function could be located in a different module
 pub fn trees_api(cfg: &mut web::ServiceConfig) {
   cfg.service(
@@ -250,6 +756,15 @@ pub fn trees_api(cfg: &mut web::ServiceConfig) {
       .service(journey_script)
       .service(get_journey)
       .service(list_scripts)
-      .service(get_journey_transactions),
+      .service(outcome_cache_stats)
+      .service(get_journey_transactions)
+      .service(get_journey_stats)
+      .service(journey_replay)
+      .service(journey_script_logs)
+      .service(journey_revisions)
+      .service(journey_diff)
+      .service(refresh_journeys)
+      .service(journey_changes)
+      .service(journey_changes_ws),
   );
 }
@@ -0,0 +1,264 @@
+use crate::ping_logs::logs::ResultingLog;
+use crate::trees::journeys::{ReactFlowEdge, Tree};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+const TOP_FAILING_PATHS: usize = 10;
+
+#[derive(Serialize)]
+pub struct OutcomeStats {
+  outcome: String,
+  count: usize,
+  percentage: f64,
+}
+
+#[derive(Serialize)]
+pub struct NodeStats {
+  node_id: String,
+  display_name: String,
+  visits: usize,
+  failures: usize,
+  error_rate: f64,
+  outcomes: Vec<OutcomeStats>,
+}
+
+#[derive(Serialize)]
+pub struct PathStats {
+  path: Vec<String>,
+  count: usize,
+  last_seen: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct JourneyStats {
+  transactions: usize,
+  failed_transactions: usize,
+  nodes: Vec<NodeStats>,
+  top_failing_paths: Vec<PathStats>,
+}
+
+struct Visit {
+  node_id: String,
+  display_name: String,
+  outcome: String,
+  timestamp: DateTime<Utc>,
+}
+
+fn percentage(part: usize, whole: usize) -> f64 {
+  if whole == 0 {
+    0.0
+  } else {
+    part as f64 * 100.0 / whole as f64
+  }
+}
+
+/// Outcome counts keyed by (node id, outcome), matching an edge's source and handle.
+pub type EdgeTraffic = HashMap<(String, String), usize>;
+
+/// Union-find over the ids requests carry: two requests belong to one login attempt when they
+/// share an id, directly or through other requests of the attempt.
+#[derive(Default)]
+struct LinkedIds {
+  /// Each id that was merged into another group, pointing towards that group's root.
+  parent: HashMap<String, String>,
+}
+
+impl LinkedIds {
+  fn root(&self, id: &str) -> String {
+    let mut root = id;
+    while let Some(parent) = self.parent.get(root) {
+      root = parent;
+    }
+    root.to_string()
+  }
+
+  fn link(&mut self, ids: &[String]) {
+    let Some((first, rest)) = ids.split_first() else {
+      return;
+    };
+    let root = self.root(first);
+    for id in rest {
+      let other = self.root(id);
+      if other != root {
+        self.parent.insert(other, root.clone());
+      }
+    }
+  }
+}
+
+/// The ids that tie a request to its login attempt: its tracking ids, or its transaction id
+/// when it has none.
+fn attempt_ids(log: &ResultingLog) -> Vec<String> {
+  match log.payload.tracking_ids() {
+    [] => vec![log.payload.transaction_id().unwrap_or_default().to_string()],
+    tracking_ids => tracking_ids.to_vec(),
+  }
+}
+
+/// Folds node outcome logs for one tree into per-node outcome counts and failing paths.
+///
+/// A login attempt spans several requests whose tracking ids only partly overlap, so visits are
+/// grouped by every id their requests share and ordered by timestamp to rebuild the path each
+/// attempt took.
+pub fn aggregate(tree: &Tree, logs: Vec<ResultingLog>) -> (JourneyStats, EdgeTraffic) {
+  let logs: Vec<(Vec<String>, ResultingLog)> = logs
+    .into_iter()
+    .map(|log| (attempt_ids(&log), log))
+    .collect();
+  let mut linked = LinkedIds::default();
+  logs.iter().for_each(|(ids, _)| linked.link(ids));
+
+  let mut attempts: HashMap<String, Vec<Visit>> = HashMap::new();
+  logs.into_iter().for_each(|(ids, log)| {
+    let attempt = linked.root(&ids[0]);
+
+    if let Some(info) = log.payload.node_outcome()
+      && let Some(timestamp) = log.timestamp
+      && let Some(node_id) = tree.resolve_node_id(
+        info.node_id.as_deref(),
+        info.tree_name.as_deref(),
+        &info.display_name,
+      )
+    {
+      attempts.entry(attempt).or_default().push(Visit {
+        node_id,
+        display_name: info.display_name,
+        outcome: info.node_outcome,
+        timestamp,
+      });
+    }
+  });
+
+  let mut traffic: EdgeTraffic = HashMap::new();
+  let mut nodes: HashMap<String, (String, HashMap<String, usize>)> = HashMap::new();
+  let mut failing_paths: HashMap<Vec<String>, PathStats> = HashMap::new();
+  let mut failed_transactions = 0;
+
+  for visits in attempts.values_mut() {
+    visits.sort_by_key(|visit| visit.timestamp);
+
+    for visit in visits.iter() {
+      *traffic
+        .entry((visit.node_id.clone(), visit.outcome.clone()))
+        .or_default() += 1;
+
+      *nodes
+        .entry(visit.node_id.clone())
+        .or_insert_with(|| (visit.display_name.clone(), HashMap::new()))
+        .1
+        .entry(visit.outcome.clone())
+        .or_default() += 1;
+    }
+
+    let failed = visits
+      .iter()
+      .any(|visit| tree.is_failing_outcome(&visit.node_id, &visit.outcome));
+
+    if failed {
+      failed_transactions += 1;
+      let path: Vec<String> = visits
+        .iter()
+        .map(|visit| format!("{} ({})", visit.display_name, visit.outcome))
+        .collect();
+      let last_seen = visits
+        .last()
+        .map(|visit| visit.timestamp)
+        .unwrap_or_default();
+
+      let stats = failing_paths.entry(path.clone()).or_insert(PathStats {
+        path,
+        count: 0,
+        last_seen,
+      });
+      stats.count += 1;
+      stats.last_seen = stats.last_seen.max(last_seen);
+    }
+  }
+
+  traffic.insert(("startNode".to_string(), "ok".to_string()), attempts.len());
+
+  let mut node_stats: Vec<NodeStats> = nodes
+    .into_iter()
+    .map(|(node_id, (display_name, outcomes))| {
+      let visits = outcomes.values().sum();
+      let failures = outcomes
+        .iter()
+        .filter(|(outcome, _)| tree.is_failing_outcome(&node_id, outcome))
+        .map(|(_, count)| count)
+        .sum();
+
+      let mut outcomes: Vec<OutcomeStats> = outcomes
+        .into_iter()
+        .map(|(outcome, count)| OutcomeStats {
+          outcome,
+          count,
+          percentage: percentage(count, visits),
+        })
+        .collect();
+      outcomes.sort_by_key(|outcome| Reverse(outcome.count));
+
+      NodeStats {
+        node_id,
+        display_name,
+        visits,
+        failures,
+        error_rate: percentage(failures, visits),
+        outcomes,
+      }
+    })
+    .collect();
+  node_stats.sort_by_key(|node| Reverse(node.visits));
+
+  let mut top_failing_paths: Vec<PathStats> = failing_paths.into_values().collect();
+  top_failing_paths.sort_by(|a, b| b.count.cmp(&a.count).then(b.last_seen.cmp(&a.last_seen)));
+  top_failing_paths.truncate(TOP_FAILING_PATHS);
+
+  (
+    JourneyStats {
+      transactions: attempts.len(),
+      failed_transactions,
+      nodes: node_stats,
+      top_failing_paths,
+    },
+    traffic,
+  )
+}
+
+/// Turns a flow's edges into a heatmap of how often each was taken.
+pub fn overlay_traffic(tree: &Tree, edges: &mut [ReactFlowEdge], traffic: &EdgeTraffic) {
+  let busiest = traffic.values().copied().max().unwrap_or_default();
+
+  edges.iter_mut().for_each(|edge| {
+    let count = traffic
+      .get(&(edge.source().to_string(), edge.source_handle().to_string()))
+      .copied()
+      .unwrap_or_default();
+    let failing = tree.is_failing_outcome(edge.source(), edge.source_handle());
+    edge.overlay_traffic(count, busiest, failing);
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+  }
+
+  #[test]
+  fn requests_sharing_any_tracking_id_are_one_attempt() {
+    let mut linked = LinkedIds::default();
+    linked.link(&ids(&["b", "a"]));
+    linked.link(&ids(&["c"]));
+    linked.link(&ids(&["d", "c"]));
+    linked.link(&ids(&["c", "b"]));
+    linked.link(&ids(&["x"]));
+
+    let root = linked.root("a");
+    assert!(["b", "c", "d"].iter().all(|id| linked.root(id) == root));
+    assert_ne!(linked.root("x"), root);
+  }
+}
@@ -1,5 +1,6 @@
 use crate::errors::ShowMeErrors;
-use reqwest::Client;
+use crate::realms::realm_path;
+use crate::ping_logs::client::LogClient;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use futures_util::future::JoinAll;
@@ -113,15 +114,19 @@ pub struct RichScript {
 }
 
 pub async fn get_rich_script(
-  client: &Client,
+  client: &LogClient,
   dom: &str,
   token_str: &str,
+  realm: &str,
   script_config: &ScriptConfig,
 ) -> Result<RichScript, ShowMeErrors> {
   let script_txt = &client
-    .get(format!("{dom}/am/json/alpha/scripts/{}", script_config.id))
-    .header("authorization", format!("Bearer {}", token_str))
-    .send()
+    .send(
+      client
+        .http()
+        .get(format!("{dom}/am/json/{}/scripts/{}", realm_path(realm), script_config.id))
+        .header("authorization", format!("Bearer {}", token_str)),
+    )
     .await?
     .bytes()
     .await?;
@@ -136,16 +141,20 @@ pub async fn get_rich_script(
 }
 
 pub async fn list_scripts(
-  client: &Client,
+  client: &LogClient,
   dom: &str,
   token_str: &str,
+  realm: &str,
 ) -> Result<HashMap<String, ScriptConfig>, ShowMeErrors> {
   let script_list = &client
-    .post(format!("{dom}/am/json/realms/root/realms/alpha/realm-config/authentication/authenticationtrees/nodes/ScriptedDecisionNode?_action=schema"))
-    .body("{}")
-    .header("authorization", format!("Bearer {}", token_str))
-    .header("Accept-API-Version", "protocol=2.1,resource=1.0")
-    .send()
+    .send(
+      client
+        .http()
+        .post(format!("{dom}/am/json/{}/realm-config/authentication/authenticationtrees/nodes/ScriptedDecisionNode?_action=schema", realm_path(realm)))
+        .body("{}")
+        .header("authorization", format!("Bearer {}", token_str))
+        .header("Accept-API-Version", "protocol=2.1,resource=1.0"),
+    )
     .await?
     .bytes()
     .await?;
@@ -0,0 +1,54 @@
+//! Records a `--mock` session to a cassette and checks `--replay` serves the app the same answers
+//! without the tenant, and without the credentials used to record it.
+
+mod common;
+
+use common::MockApp;
+use std::fs;
+
+const LOGIN_FAILURE: &str = "9f1c2b3a-7d6e-4f5a-8b9c-0d1e2f3a4b5c-1377";
+const PATHS: [&str; 5] = [
+  "/journey",
+  "/journey/Login/transactions",
+  "/journey/Login/script-logs?transaction_id=9f1c2b3a-7d6e-4f5a-8b9c-0d1e2f3a4b5c-1377",
+  "/logs/search?sources=am-core&query_filter=/payload/logger%20sw%20%22scripts.%22",
+  "/logs/search?sources=am-access&query_filter=/payload/principal%20eq%20%22demo%22",
+];
+
+#[actix_web::test]
+async fn replay_answers_what_was_recorded() {
+  let dir = std::env::temp_dir().join(format!("show-me-logs-cassette-{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let cassette = dir.join("session.jsonl");
+  let cassette = cassette.to_str().unwrap();
+
+  let recorded = {
+    let app = MockApp::start_with(&["--mock", "--record", cassette]).await;
+    let mut answers = Vec::new();
+    for path in PATHS {
+      answers.push(app.get_ok(path).await);
+    }
+    answers
+  };
+
+  let recording = fs::read_to_string(cassette).unwrap();
+  assert!(recording.contains(LOGIN_FAILURE));
+  assert!(!recording.contains("mock-access-token"));
+  assert!(!recording.contains("mock-log-key"));
+  assert!(!recording.contains("mock-log-secret"));
+  // Who signed in and from where stays out of the log records.
+  assert!(!recording.contains("203.0.113.7"));
+  assert!(!recording.contains("\"demo\""));
+  assert!(!recording.contains("%22demo%22"));
+
+  let app = MockApp::start_with(&["--replay", cassette]).await;
+  for (path, answer) in PATHS.iter().zip(&recorded) {
+    assert_eq!(
+      &app.get_ok(path).await,
+      answer,
+      "GET {path} replayed differently"
+    );
+  }
+
+  fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,95 @@
+//! Starts the binary against a stand-in tenant for the integration tests.
+// Each test crate uses its own share of these helpers.
+#![allow(dead_code)]
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+pub const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The app and its mock tenant, killed when dropped.
+pub struct MockApp {
+  child: Child,
+  pub api: String,
+  pub upstream: String,
+  pub client: reqwest::Client,
+}
+
+fn free_port() -> u16 {
+  TcpListener::bind(("127.0.0.1", 0))
+    .and_then(|listener| listener.local_addr())
+    .expect("no free port")
+    .port()
+}
+
+impl MockApp {
+  pub async fn start() -> Self {
+    MockApp::start_with(&["--mock"]).await
+  }
+
+  /// Runs the binary with `args`, e.g. `--mock --record <path>` or `--replay <path>`.
+  pub async fn start_with(args: &[&str]) -> Self {
+    let (port, mock_port) = (free_port(), free_port());
+    let child = Command::new(env!("CARGO_BIN_EXE_show-me-logs"))
+      .args(args)
+      .env("PORT", port.to_string())
+      .env("MOCK_PORT", mock_port.to_string())
+      .env("CASSETTE_PORT", free_port().to_string())
+      .env_remove("MOCK_FIXTURES")
+      .env_remove("TREE_HISTORY_DIR")
+      .stdout(Stdio::null())
+      .spawn()
+      .expect("could not start show-me-logs");
+
+    let app = MockApp {
+      child,
+      api: format!("http://127.0.0.1:{port}/api"),
+      upstream: format!("http://127.0.0.1:{mock_port}"),
+      client: reqwest::Client::new(),
+    };
+
+    let started = Instant::now();
+    while app
+      .client
+      .get(format!("{}/env", app.api))
+      .send()
+      .await
+      .is_err()
+    {
+      assert!(
+        started.elapsed() < STARTUP_TIMEOUT,
+        "show-me-logs {args:?} never came up"
+      );
+      actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+    }
+    app
+  }
+
+  pub async fn get(&self, path: &str) -> (StatusCode, Value) {
+    let response = self
+      .client
+      .get(format!("{}{path}", self.api))
+      .send()
+      .await
+      .expect("request failed");
+    let status = response.status();
+    let body = response.bytes().await.expect("no body");
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+  }
+
+  pub async fn get_ok(&self, path: &str) -> Value {
+    let (status, body) = self.get(path).await;
+    assert_eq!(status, StatusCode::OK, "GET {path} returned {body}");
+    body
+  }
+}
+
+impl Drop for MockApp {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
@@ -0,0 +1,291 @@
+//! Runs the binary in `--mock` mode and exercises its API end to end against the built-in
+//! fixtures, from token exchange to log paging and script log attribution.
+
+mod common;
+
+use common::{MockApp, STARTUP_TIMEOUT};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+const LOGIN_SUCCESS: &str = "9f1c2b3a-7d6e-4f5a-8b9c-0d1e2f3a4b5c-1201";
+const LOGIN_FAILURE: &str = "9f1c2b3a-7d6e-4f5a-8b9c-0d1e2f3a4b5c-1377";
+const CHECK_RISK_NODE: &str = "6c7d1a2e-1f0b-4c5e-9a33-0d1f2e3a4b03";
+const SEND_OTP_NODE: &str = "8e2d4c6b-3a1f-4b9e-8d7c-5f6e7a8b9c01";
+
+#[actix_web::test]
+async fn environment_signs_in_and_reaches_the_logs_api() {
+  let app = MockApp::start().await;
+
+  let environments = app.get_ok("/env").await;
+  let mock = &environments[0];
+  assert_eq!(mock["name"], "mock");
+  assert_eq!(mock["token"], true);
+  assert_eq!(mock["logs"], true);
+  assert_eq!(mock["trees"], 2);
+  assert_eq!(mock["errors"], Value::Array(vec![]));
+}
+
+#[actix_web::test]
+async fn lists_journeys_and_realms() {
+  let app = MockApp::start().await;
+
+  assert_eq!(
+    app.get_ok("/journey").await,
+    serde_json::json!(["Login", "MFA"])
+  );
+  assert_eq!(
+    app.get_ok("/journey?starts_with=M").await,
+    serde_json::json!(["MFA"])
+  );
+  assert_eq!(app.get_ok("/realms").await[0]["realm"], "alpha");
+}
+
+#[actix_web::test]
+async fn journey_transactions_page_through_every_outcome() {
+  let app = MockApp::start().await;
+
+  // Seven outcomes of the Login tree over pages of three. Two more in the fixtures, one with an
+  // unreadable timestamp and one without a transaction id, are left out rather than failing the list.
+  let transactions = app.get_ok("/journey/Login/transactions").await;
+  let ids: Vec<&str> = transactions
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|transaction| transaction["transaction_id"].as_str().unwrap())
+    .collect();
+  assert_eq!(ids.len(), 7);
+  assert!(ids[..3].iter().all(|id| id.starts_with(LOGIN_SUCCESS)));
+  assert!(ids[3..].iter().all(|id| id.starts_with(LOGIN_FAILURE)));
+
+  let windowed = app
+    .get_ok(
+      "/journey/Login/transactions?begin_time=2025-03-04T10:04:00Z&end_time=2025-03-04T11:00:00Z",
+    )
+    .await;
+  assert_eq!(windowed.as_array().unwrap().len(), 4);
+}
+
+#[actix_web::test]
+async fn flow_expands_inner_journeys_from_node_configs() {
+  let app = MockApp::start().await;
+
+  let flow = app
+    .get_ok(&format!(
+      "/journey/Login/flow?transaction_id={LOGIN_FAILURE}&expand_inner=true"
+    ))
+    .await;
+  let node_ids: Vec<&str> = flow["nodes"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .filter_map(|node| node["id"].as_str())
+    .collect();
+  assert!(node_ids.contains(&CHECK_RISK_NODE));
+  assert!(
+    node_ids.iter().any(|id| id.contains(SEND_OTP_NODE)),
+    "inner MFA journey was not expanded: {node_ids:?}"
+  );
+
+  let (status, _) = app.get("/journey/Nope/flow").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn search_evaluates_query_filters_and_fields() {
+  let app = MockApp::start().await;
+
+  let script_lines = app
+    .get_ok("/logs/search?sources=am-core&query_filter=/payload/logger%20sw%20%22scripts.%22")
+    .await;
+  assert_eq!(script_lines.as_array().unwrap().len(), 3);
+
+  let projected = app
+    .get_ok("/logs/search?sources=am-authentication&query_filter=/payload/result%20eq%20%22FAILED%22&fields=timestamp,payload/principal")
+    .await;
+  assert_eq!(
+    projected,
+    serde_json::json!([{
+      "timestamp": "2025-03-04T10:05:00.951Z",
+      "payload": { "principal": ["demo"] },
+    }])
+  );
+
+  let (status, _) = app
+    .get("/logs/search?query_filter=/payload/logger%20sw")
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // Escapes survive the trip to the logs API, which parses the filter as rendered.
+  let escaped = app
+    .get_ok("/logs/search?sources=am-core&query_filter=/payload/message%20eq%20%22risk%5Cu0020score%5Ct87%22%20or%20/payload/message%20eq%20%22risk%5Cu0020score%2087%22")
+    .await;
+  assert_eq!(escaped.as_array().unwrap().len(), 1);
+
+  let nested = format!("{}true{}", "(".repeat(40), ")".repeat(40));
+  let (status, _) = app
+    .get(&format!("/logs/search?query_filter={nested}"))
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // Malformed relative times are rejected, never panicking or landing in the future.
+  for begin_time in ["-1%C3%A9", "--5h", "-h", "-99999999999w"] {
+    let (status, _) = app
+      .get(&format!("/logs/search?begin_time={begin_time}"))
+      .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "begin_time={begin_time}");
+  }
+}
+
+#[actix_web::test]
+async fn replay_after_a_capped_one_still_fetches_the_rest() {
+  let app = MockApp::start().await;
+  let replay = format!("/journey/Login/replay?transaction_id={LOGIN_SUCCESS}");
+
+  let capped = app.get_ok(&format!("{replay}&max_records=1")).await;
+  assert_eq!(capped.as_array().unwrap().len(), 1);
+
+  // The capped fetch must not pass for everything the tracking id has.
+  let whole = app.get_ok(&replay).await;
+  assert_eq!(whole.as_array().unwrap().len(), 3, "{whole}");
+}
+
+#[actix_web::test]
+async fn script_logs_are_attributed_to_their_nodes() {
+  let app = MockApp::start().await;
+
+  let bundles = app
+    .get_ok(&format!(
+      "/journey/Login/script-logs?transaction_id={LOGIN_FAILURE}"
+    ))
+    .await;
+  let visits = bundles["nodes"][CHECK_RISK_NODE]["visits"]
+    .as_array()
+    .unwrap();
+  assert_eq!(visits.len(), 1);
+  assert_eq!(visits[0]["outcome"], "high");
+  assert_eq!(visits[0]["logs"][0]["payload"]["message"], "risk score 87");
+
+  // The inner journey's script ran outside any node of Login.
+  let unattributed = bundles["unattributed"].as_array().unwrap();
+  assert_eq!(unattributed.len(), 1);
+  assert_eq!(unattributed[0]["payload"]["message"], "sending otp");
+}
+
+#[actix_web::test]
+async fn scripts_are_listed_from_the_node_schema() {
+  let app = MockApp::start().await;
+
+  // The script list is loaded in the background after startup.
+  let started = Instant::now();
+  let scripts = loop {
+    let scripts = app.get_ok("/journey/scripts").await;
+    if scripts
+      .as_object()
+      .is_some_and(|scripts| !scripts.is_empty())
+    {
+      break scripts;
+    }
+    assert!(
+      started.elapsed() < STARTUP_TIMEOUT,
+      "scripts were never listed"
+    );
+    actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+  };
+  assert_eq!(scripts.as_object().unwrap().len(), 3);
+  assert_eq!(
+    scripts["Check Risk Score"]["id"],
+    "3f9e8b1c-5d2a-4e7f-8a6b-1c2d3e4f5a01"
+  );
+}
+
+#[actix_web::test]
+async fn prometheus_metrics_are_parsed_and_filtered() {
+  let app = MockApp::start().await;
+
+  let metrics = app
+    .get_ok("/monitoring/am?names=am_http_*,am_tree_total&labels=realm=/alpha")
+    .await;
+  let families = metrics["families"].as_array().unwrap();
+  assert_eq!(families.len(), 1);
+  assert_eq!(families[0]["kind"], "counter");
+  assert_eq!(families[0]["samples"].as_array().unwrap().len(), 4);
+
+  let histogram = &app
+    .get_ok("/monitoring/am?names=am_http_request_seconds")
+    .await["families"][0];
+  assert_eq!(histogram["kind"], "histogram");
+  assert_eq!(histogram["samples"][0]["buckets"][4]["le"], "+Inf");
+  assert_eq!(histogram["samples"][0]["count"], 1942.0);
+
+  // Nothing to take a rate against on the first scrape.
+  let rates = app.get_ok("/monitoring/am/rates").await;
+  assert_eq!(rates["since"], Value::Null);
+  assert_eq!(rates["rates"], Value::Array(vec![]));
+
+  let (status, _) = app.get("/monitoring/am?labels=tree").await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn am_metrics_are_grouped_by_tree_and_node() {
+  let app = MockApp::start().await;
+
+  let trees = app.get_ok("/monitoring/am/trees?tree=Login").await;
+  assert_eq!(
+    trees["trees"],
+    serde_json::json!([{
+      "realm": "/alpha",
+      "tree": "Login",
+      "tree_type": "standard",
+      "outcomes": { "success": 1790.0, "failure": 92.0 },
+      "per_second": {},
+    }])
+  );
+
+  let nodes = app.get_ok("/monitoring/am/nodes?tree=Login").await;
+  let nodes = nodes["nodes"].as_array().unwrap();
+  assert_eq!(nodes.len(), 2);
+  assert_eq!(nodes[0]["node_type"], "DataStoreDecisionNode");
+  assert_eq!(nodes[0]["count"], 1882.0);
+  assert_eq!(nodes[0]["quantiles"][1]["value"], 0.048);
+}
+
+#[actix_web::test]
+async fn mock_tenant_rejects_bad_credentials() {
+  let app = MockApp::start().await;
+
+  let token = app
+    .client
+    .post(format!("{}/am/oauth2/access_token", app.upstream))
+    .form(&[
+      ("client_id", "service-account"),
+      ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+      ("assertion", "not.a.jwt"),
+    ])
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(token.status(), StatusCode::BAD_REQUEST);
+
+  let trees = app
+    .client
+    .get(format!(
+      "{}/am/json/realms/root/realms/alpha/realm-config/authentication/authenticationtrees/trees",
+      app.upstream
+    ))
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(trees.status(), StatusCode::UNAUTHORIZED);
+
+  let sources = app
+    .client
+    .get(format!("{}/monitoring/logs/sources", app.upstream))
+    .header("x-api-key", "mock-log-key")
+    .header("x-api-secret", "wrong")
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(sources.status(), StatusCode::UNAUTHORIZED);
+}
@@ -9,9 +9,9 @@ import ListItem from "@mui/material/ListItem";
 import ListItemButton from "@mui/material/ListItemButton";
 import ListItemIcon from "@mui/material/ListItemIcon";
 import ListItemText from "@mui/material/ListItemText";
-import { useState } from "react";
+import { useEffect, useState } from "react";
 import "./App.css";
-import useSWR, { type Fetcher } from "swr";
+import useSWR, { type Fetcher, useSWRConfig } from "swr";
 import { PingNode } from "./CustomNodes.tsx";
 import type { Root } from "./types";
 import { ReactFlow, useOnSelectionChange } from "@xyflow/react";
@@ -81,13 +81,18 @@ const ManualLogs = () => {
 
 const WatchLogs = () => {
   const [watching, setWatching] = useState<string>("Error");
+  const [watchId, setWatchId] = useState<string>();
   const { data: watchData } = useSWR(
-    `${document.URL.includes("5173") ? "http://localhost:8081" : ""}/api/logs/watch?filters=${watching ?? "All"}`,
+    watchId === undefined
+      ? null
+      : `${document.URL.includes("5173") ? "http://localhost:8081" : ""}/api/logs/watch/${watchId}?filters=${watching ?? "All"}`,
     simpleJsonFetcher
   );
   return (
     <>
       <h3>Watching</h3>
+      <label htmlFor={"watchId"}>Watch id </label>
+      <input id={"watchId"} onChange={(event) => setWatchId(event.target.value)} />
       <label>logs types to keep</label>
       <select
         value={watching}
@@ -111,6 +116,13 @@ const nodeTypes = {
   ping: PingNode,
 };
 
+const visitColours: Record<string, string> = {
+  visited: "#c8e6c9",
+  errored: "#ffcdd2",
+  terminal: "#bbdefb",
+  not_visited: "#ffffff",
+};
+
 const ReactFlowComp = () => {
   const [startsWith, setStartsWith] = useState<string>("");
   const [endsWith, setEndsWith] = useState<string>("");
@@ -125,6 +137,20 @@ const ReactFlowComp = () => {
   );
   const [selectedJourney, setReselectedJourney] = useState<string>();
 
+  // Journeys edited in the admin console show up without a reload.
+  const { mutate } = useSWRConfig();
+  useEffect(() => {
+    const host = document.URL.includes("5173")
+      ? "localhost:8081"
+      : window.location.host;
+    const socket = new WebSocket(`ws://${host}/api/journey/changes/ws`);
+    socket.onmessage = () =>
+      mutate(
+        (key) => typeof key === "string" && key.includes("/api/journey")
+      );
+    return () => socket.close();
+  }, [mutate]);
+
   const [selectedNode, setSelectedNode] = useState<string | undefined>(
     undefined
   );
@@ -144,10 +170,16 @@ const ReactFlowComp = () => {
     },
   });
 
+  const [expandInner, setExpandInner] = useState<boolean>(false);
+
+  const flowSearch = new URLSearchParams({
+    ...(transactionId !== undefined ? { transaction_id: transactionId } : {}),
+    ...(expandInner ? { expand_inner: "true" } : {}),
+  });
   const { data: journeyFlow } = useSWR(
     selectedJourney === undefined
       ? null
-      : `${document.URL.includes("5173") ? "http://localhost:8081" : ""}/api/journey/${selectedJourney}/flow${transactionId !== undefined ? `?transaction_id=${transactionId}` : ""}`,
+      : `${document.URL.includes("5173") ? "http://localhost:8081" : ""}/api/journey/${selectedJourney}/flow?${flowSearch.toString()}`,
     jsonFetcher
   );
 
@@ -195,11 +227,20 @@ const ReactFlowComp = () => {
   console.info(scriptLogs);
 
   const nodes = journeyFlow?.nodes.map(
-    (node: { id: string; data: { name?: string }; handles: object[] }) => ({
+    (node: {
+      id: string;
+      data: { name?: string; visitStatus?: string };
+      handles: object[];
+      width?: number;
+      height?: number;
+    }) => ({
       ...node,
       type: "ping",
       style: {
-        height: Math.max(80, node.handles.length * 20 + 20),
+        ...(node.width !== undefined
+          ? { width: node.width, height: node.height }
+          : { height: Math.max(80, node.handles.length * 20 + 20) }),
+        background: visitColours[node.data.visitStatus ?? "not_visited"],
       },
       data: {
         handles: node.handles,
@@ -271,6 +312,13 @@ const ReactFlowComp = () => {
           value={startsWith}
           onChange={(e) => setStartsWith(e.target.value)}
         />
+        <label htmlFor="expandInner">Expand inner journeys:</label>
+        <input
+          type="checkbox"
+          id="expandInner"
+          checked={expandInner}
+          onChange={(e) => setExpandInner(e.target.checked)}
+        />
         <label htmlFor="endsWith">Ends With:</label>
         <input
           type="text"
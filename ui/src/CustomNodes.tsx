@@ -9,6 +9,11 @@ export type PingNode = Node<
     name?: string;
     type?: string;
     scriptContent: [object, { script: string }];
+    visitStatus?: string;
+    visitCount?: string;
+    firstVisit?: string;
+    lastVisit?: string;
+    outcome?: string;
   },
   "ping"
 >;
@@ -73,7 +78,15 @@ export const PingNode = ({ data }: NodeProps<PingNode>) => {
           </Handle>
         ))}
       </div>
-      <div>{data?.name ?? "ToDo"}</div>
+      <div
+        title={
+          data.firstVisit
+            ? `Ran ${data.visitCount} time(s), ${data.firstVisit} to ${data.lastVisit}${data.outcome ? ` -> ${data.outcome}` : ""}`
+            : "Not visited"
+        }
+      >
+        {data?.name ?? "ToDo"}
+      </div>
       <div>{data?.type ?? "ToDo"}</div>
       <div style={{ position: "absolute", bottom: 0 }}>
         <Button
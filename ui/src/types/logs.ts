@@ -1,6 +1,6 @@
 export interface Root {
   result: Result[];
-  pagedResultCooke: any;
+  pagedResultsCookie: string | null;
   totalPagedResultsPolicy: string;
   totalPagedResults: number;
   remainingPagedResults: number;